@@ -9,13 +9,14 @@
 
 use crate::{
     client::internal::ButtplugClientMessageStateShared,
-    core::messages::{ButtplugMessage, ButtplugMessageUnion},
+    core::messages::{ButtplugMessage, ButtplugMessageUnion, MessageIdGenerator},
 };
 use std::collections::HashMap;
 
+#[derive(Default)]
 pub struct ClientConnectorMessageSorter {
     future_map: HashMap<u32, ButtplugClientMessageStateShared>,
-    current_id: u32,
+    id_generator: MessageIdGenerator,
 }
 
 impl ClientConnectorMessageSorter {
@@ -24,9 +25,8 @@ impl ClientConnectorMessageSorter {
         msg: &mut ButtplugMessageUnion,
         state: &ButtplugClientMessageStateShared,
     ) {
-        msg.set_id(self.current_id);
-        self.future_map.insert(self.current_id, state.clone());
-        self.current_id += 1;
+        let id = self.id_generator.stamp(msg);
+        self.future_map.insert(id, state.clone());
     }
 
     pub fn maybe_resolve_message(&mut self, msg: &ButtplugMessageUnion) -> bool {
@@ -43,12 +43,3 @@ impl ClientConnectorMessageSorter {
         }
     }
 }
-
-impl Default for ClientConnectorMessageSorter {
-    fn default() -> Self {
-        Self {
-            future_map: HashMap::new(),
-            current_id: 1,
-        }
-    }
-}
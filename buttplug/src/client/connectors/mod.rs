@@ -10,6 +10,8 @@ pub mod messagesorter;
 #[cfg(any(feature = "client-ws", feature = "client-ws-ssl"))]
 pub mod websocket;
 
+#[cfg(feature = "server")]
+use crate::core::messages::{self, ButtplugMessage};
 #[cfg(feature = "server")]
 use crate::server::ButtplugServer;
 use crate::{
@@ -103,13 +105,23 @@ impl ButtplugClientConnector for ButtplugEmbeddedClientConnector {
     }
 
     async fn disconnect(&mut self) -> Result<(), ButtplugClientConnectorError> {
+        self.server.disconnect().await;
         Ok(())
     }
 
     async fn send(&mut self, msg: &ButtplugMessageUnion, state: &ButtplugClientMessageStateShared) {
-        let ret_msg = self.server.send_message(msg).await;
+        // Server errors are relayed back to the client as Error messages, same
+        // as they would be over a remote connection.
+        let ret_msg = match self.server.send_message(msg).await {
+            Ok(ret_msg) => ret_msg,
+            Err(err) => {
+                let mut err_msg = messages::Error::from(err);
+                err_msg.set_id(msg.get_id());
+                err_msg.as_union()
+            }
+        };
         let mut waker_state = state.lock().unwrap();
-        waker_state.set_reply(ret_msg.unwrap());
+        waker_state.set_reply(ret_msg);
     }
 
     fn get_event_receiver(&mut self) -> Receiver<ButtplugMessageUnion> {
@@ -233,8 +245,13 @@ impl ButtplugRemoteClientConnectorHelper {
                                 remote_send = Some(s);
                             }
                             ButtplugRemoteClientConnectorMessage::Text(t) => {
-                                let array: Vec<ButtplugMessageUnion> =
-                                    serde_json::from_str(&t.clone()).unwrap();
+                                let array = match ButtplugMessageUnion::try_from_json(&t) {
+                                    Ok(array) => array,
+                                    Err(e) => {
+                                        error!("Cannot parse incoming message: {}", e);
+                                        continue;
+                                    }
+                                };
                                 for smsg in array {
                                     if !sorter.maybe_resolve_message(&smsg) {
                                         info!("Sending event!");
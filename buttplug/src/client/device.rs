@@ -104,9 +104,9 @@ impl ButtplugClientDevice {
                 ButtplugError::from(_err),
             )),
             _ => Err(ButtplugClientError::ButtplugError(
-                ButtplugError::ButtplugMessageError(ButtplugMessageError {
-                    message: "Got unexpected message type.".to_owned(),
-                }),
+                ButtplugError::ButtplugMessageError(ButtplugMessageError::UnexpectedType(
+                    "Got unexpected message type.".to_owned(),
+                )),
             )),
         }
     }
@@ -14,17 +14,20 @@ use super::{
     device::ButtplugClientDevice,
     ButtplugClientEvent, ButtplugClientResult,
 };
-use crate::core::messages::{ButtplugMessageUnion, DeviceList, DeviceMessageInfo};
+use crate::core::messages::{
+    ButtplugMessage, ButtplugMessageUnion, DeviceList, DeviceMessageInfo, Ping,
+};
 use async_std::{
-    future::Future,
+    future::{self, Future},
     prelude::{FutureExt, StreamExt},
     sync::{channel, Receiver, Sender},
-    task::{Context, Poll, Waker},
+    task::{self, Context, Poll, Waker},
 };
 use core::pin::Pin;
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 /// Struct used for waiting on replies from the server.
@@ -166,6 +169,11 @@ pub enum ButtplugClientMessage {
     /// Bundled future should have reply set and waker called when this is
     /// finished.
     Message(ButtplugClientMessageFuturePair),
+    /// Start pinging the server, given the max_ping_time (in milliseconds)
+    /// from its ServerInfo. Pings are sent at half that interval until the
+    /// loop exits. A max_ping_time of 0 means the server doesn't require
+    /// pings, and nothing is sent.
+    StartPingTimer(u32),
 }
 
 pub enum ButtplugClientDeviceEvent {
@@ -178,6 +186,7 @@ enum StreamReturn {
     ConnectorMessage(ButtplugMessageUnion),
     ClientMessage(ButtplugClientMessage),
     DeviceMessage(ButtplugClientMessageFuturePair),
+    Ping,
     Disconnect,
 }
 
@@ -190,6 +199,8 @@ struct ButtplugClientEventLoop {
     client_receiver: Receiver<ButtplugClientMessage>,
     connector: Box<dyn ButtplugClientConnector>,
     connector_receiver: Receiver<ButtplugMessageUnion>,
+    ping_interval: Option<Duration>,
+    next_ping: Option<Instant>,
 }
 
 impl ButtplugClientEventLoop {
@@ -233,6 +244,8 @@ impl ButtplugClientEventLoop {
                                 client_receiver,
                                 connector_receiver: connector.get_event_receiver(),
                                 connector,
+                                ping_interval: None,
+                                next_ping: None,
                             })
                         }
                     }
@@ -316,6 +329,15 @@ impl ButtplugClientEventLoop {
                 info!("Finised setting waker!");
                 true
             }
+            ButtplugClientMessage::StartPingTimer(max_ping_time) => {
+                if max_ping_time > 0 {
+                    info!("Starting ping timer, max ping time {}ms", max_ping_time);
+                    let interval = Duration::from_millis(u64::from(max_ping_time / 2));
+                    self.ping_interval = Some(interval);
+                    self.next_ping = Some(Instant::now() + interval);
+                }
+                true
+            }
             ButtplugClientMessage::HandleDeviceList(device_list) => {
                 info!("Handling device list!");
                 for d in &device_list.devices {
@@ -370,7 +392,24 @@ impl ButtplugClientEventLoop {
                 }
             };
 
-            let stream_fut = event_future.race(client_future).race(device_future);
+            let next_ping = self.next_ping;
+            let ping_future = async move {
+                match next_ping {
+                    Some(next_ping) => {
+                        let now = Instant::now();
+                        if next_ping > now {
+                            task::sleep(next_ping - now).await;
+                        }
+                        StreamReturn::Ping
+                    }
+                    None => future::pending().await,
+                }
+            };
+
+            let stream_fut = event_future
+                .race(client_future)
+                .race(device_future)
+                .race(ping_future);
             match stream_fut.await {
                 StreamReturn::ConnectorMessage(msg) => self.parse_connector_message(msg).await,
                 StreamReturn::ClientMessage(msg) => {
@@ -383,6 +422,23 @@ impl ButtplugClientEventLoop {
                     // this device.
                     self.connector.send(&msg_fut.0, &msg_fut.1).await;
                 }
+                StreamReturn::Ping => {
+                    // Schedule off of the last deadline rather than now, so
+                    // other traffic doesn't make pings drift.
+                    if let (Some(interval), Some(next_ping)) = (self.ping_interval, self.next_ping)
+                    {
+                        self.next_ping = Some(next_ping + interval);
+                    }
+                    // Nothing waits on the reply. If pings stop getting
+                    // through, the server emits a ping error event.
+                    debug!("Sending ping to server.");
+                    self.connector
+                        .send(
+                            &Ping::default().as_union(),
+                            &ButtplugClientMessageStateShared::default(),
+                        )
+                        .await;
+                }
                 StreamReturn::Disconnect => {
                     info!("Disconnected!");
                     break;
@@ -22,8 +22,8 @@ use internal::{
 use crate::core::{
     errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     messages::{
-        ButtplugMessage, ButtplugMessageUnion, DeviceMessageInfo, LogLevel, RequestDeviceList,
-        RequestServerInfo, StartScanning,
+        ButtplugMessage, ButtplugMessageUnion, DeviceMessageInfo, LogLevel, MessageSpecVersion,
+        RequestDeviceList, RequestServerInfo, StartScanning,
     },
 };
 
@@ -281,7 +281,9 @@ impl ButtplugClient {
     async fn handshake(&mut self) -> ButtplugClientResult {
         info!("Running handshake with server.");
         match self
-            .send_message(&RequestServerInfo::new(&self.client_name, 1).as_union())
+            .send_message(
+                &RequestServerInfo::new(&self.client_name, MessageSpecVersion::Version1).as_union(),
+            )
             .await
         {
             Ok(msg) => {
@@ -289,7 +291,10 @@ impl ButtplugClient {
                 if let ButtplugMessageUnion::ServerInfo(server_info) = msg {
                     info!("Connected to {}", server_info.server_name);
                     self.server_name = Option::Some(server_info.server_name);
-                    // TODO Handle ping time in the internal event loop
+                    self.send_internal_message(ButtplugClientMessage::StartPingTimer(
+                        server_info.max_ping_time,
+                    ))
+                    .await?;
 
                     // Get currently connected devices. The event loop will
                     // handle sending the message and getting the return, and
@@ -400,9 +405,9 @@ impl ButtplugClient {
     async fn send_message_expect_ok(&mut self, msg: &ButtplugMessageUnion) -> ButtplugClientResult {
         match self.send_message(msg).await? {
             ButtplugMessageUnion::Ok(_) => Ok(()),
-            _ => Err(ButtplugClientError::from(ButtplugMessageError::new(
-                "Got non-Ok message back",
-            ))),
+            _ => Err(ButtplugClientError::from(
+                ButtplugMessageError::UnexpectedType("Got non-Ok message back".to_owned()),
+            )),
         }
     }
 
@@ -495,15 +500,18 @@ mod test {
             },
             internal::ButtplugClientMessageStateShared,
         },
-        core::messages::ButtplugMessageUnion,
+        core::messages::{
+            ButtplugMessage, ButtplugMessageUnion, DeviceList, MessageSpecVersion, Ok, ServerInfo,
+        },
     };
     use async_std::{
-        future::Future,
-        sync::{channel, Receiver},
+        future::{self, Future},
+        sync::{channel, Receiver, Sender},
         task,
     };
     use async_trait::async_trait;
     use env_logger;
+    use std::time::Duration;
 
     async fn connect_test_client<F, T>(func: F)
     where
@@ -547,6 +555,107 @@ mod test {
         }
     }
 
+    // Answers the handshake with the given max_ping_time, and reports each
+    // ping it receives on the ping channel.
+    struct ButtplugPingRecordingConnector {
+        max_ping_time: u32,
+        ping_sender: Sender<()>,
+        _event_sender: Sender<ButtplugMessageUnion>,
+        event_receiver: Option<Receiver<ButtplugMessageUnion>>,
+    }
+
+    impl ButtplugPingRecordingConnector {
+        fn new(max_ping_time: u32, ping_sender: Sender<()>) -> Self {
+            let (_event_sender, event_receiver) = channel(256);
+            Self {
+                max_ping_time,
+                ping_sender,
+                _event_sender,
+                event_receiver: Some(event_receiver),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ButtplugClientConnector for ButtplugPingRecordingConnector {
+        async fn connect(&mut self) -> Result<(), ButtplugClientConnectorError> {
+            Result::Ok(())
+        }
+
+        async fn disconnect(&mut self) -> Result<(), ButtplugClientConnectorError> {
+            Result::Ok(())
+        }
+
+        async fn send(
+            &mut self,
+            msg: &ButtplugMessageUnion,
+            state: &ButtplugClientMessageStateShared,
+        ) {
+            let mut reply = match msg {
+                ButtplugMessageUnion::RequestServerInfo(_) => ServerInfo::new(
+                    "Test Server",
+                    MessageSpecVersion::Version1,
+                    self.max_ping_time,
+                )
+                .as_union(),
+                ButtplugMessageUnion::RequestDeviceList(_) => DeviceList::default().as_union(),
+                ButtplugMessageUnion::Ping(_) => {
+                    self.ping_sender.send(()).await;
+                    Ok::default().as_union()
+                }
+                _ => Ok::default().as_union(),
+            };
+            reply.set_id(msg.get_id());
+            state.lock().unwrap().set_reply(reply);
+        }
+
+        fn get_event_receiver(&mut self) -> Receiver<ButtplugMessageUnion> {
+            self.event_receiver.take().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_client_ping_keepalive() {
+        let (ping_sender, pings) = channel(256);
+        let connector = ButtplugPingRecordingConnector::new(100, ping_sender);
+        task::block_on(async {
+            assert!(
+                ButtplugClient::run("Test Client", connector, |client| async move {
+                    // Dropping the client shuts down the event loop, so hold on to
+                    // it while waiting. The timeout only keeps a broken timer
+                    // from hanging the test.
+                    let pinged = future::timeout(Duration::from_secs(5), async {
+                        for _ in 0..3 {
+                            pings.recv().await.unwrap();
+                        }
+                    })
+                    .await;
+                    assert!(pinged.is_ok());
+                    assert!(client.connected());
+                })
+                .await
+                .is_ok()
+            );
+        });
+    }
+
+    #[test]
+    fn test_client_no_ping_when_disabled() {
+        let (ping_sender, pings) = channel(256);
+        let connector = ButtplugPingRecordingConnector::new(0, ping_sender);
+        task::block_on(async {
+            assert!(
+                ButtplugClient::run("Test Client", connector, |client| async move {
+                    task::sleep(Duration::from_millis(100)).await;
+                    assert!(client.connected());
+                })
+                .await
+                .is_ok()
+            );
+            assert!(pings.is_empty());
+        });
+    }
+
     #[test]
     fn test_failing_connection() {
         let _ = env_logger::builder().is_test(true).try_init();
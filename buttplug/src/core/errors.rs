@@ -10,6 +10,7 @@
 use super::messages::{self, ErrorCode};
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
 pub type ButtplugResult<T = ()> = Result<T, ButtplugError>;
 
@@ -49,30 +50,56 @@ impl Error for ButtplugHandshakeError {
 
 /// Message errors occur when a message is somehow malformed on creation, or
 /// received unexpectedly by a client or server.
-#[derive(Debug, Clone)]
-pub struct ButtplugMessageError {
-    pub message: String,
+#[derive(Debug, Clone, PartialEq)]
+pub enum ButtplugMessageError {
+    /// A message field contains an invalid value.
+    InvalidField { field: String, reason: String },
+    /// A message of the wrong type was received, or a message was sent in a
+    /// direction it is not allowed to travel.
+    UnexpectedType(String),
+    /// A message could not be deserialized.
+    DeserializationFailed(String),
+    /// Any other message error. Also used for message errors received from a
+    /// remote, which only carry a description.
+    Other(String),
 }
 
 impl ButtplugMessageError {
     pub fn new(message: &str) -> Self {
-        Self {
-            message: message.to_owned(),
+        ButtplugMessageError::Other(message.to_owned())
+    }
+
+    pub fn invalid_field(field: &str, reason: &str) -> Self {
+        ButtplugMessageError::InvalidField {
+            field: field.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+
+    /// Returns a description of the error, suitable for the error message of
+    /// a protocol [Error](super::messages::Error) message.
+    pub fn message(&self) -> String {
+        match *self {
+            ButtplugMessageError::InvalidField {
+                ref field,
+                ref reason,
+            } => format!("Invalid value for field {}: {}", field, reason),
+            ButtplugMessageError::UnexpectedType(ref s) => s.clone(),
+            ButtplugMessageError::DeserializationFailed(ref s) => s.clone(),
+            ButtplugMessageError::Other(ref s) => s.clone(),
         }
     }
 }
 
 impl fmt::Display for ButtplugMessageError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Message Error: {}", self.message)
+        write!(f, "Message Error: {}", self.message())
     }
 }
 
+// description() is left at its default, as it can't return the message
+// built for InvalidField. Use Display or message() instead.
 impl Error for ButtplugMessageError {
-    fn description(&self) -> &str {
-        self.message.as_str()
-    }
-
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         None
     }
@@ -116,12 +143,25 @@ impl Error for ButtplugPingError {
 #[derive(Debug, Clone)]
 pub struct ButtplugDeviceError {
     pub message: String,
+    /// Underlying error that caused this one, if any. Returned from
+    /// [Error::source].
+    source: Option<Arc<dyn Error + Send + Sync + 'static>>,
 }
 
 impl ButtplugDeviceError {
     pub fn new(message: &str) -> Self {
         Self {
             message: message.to_owned(),
+            source: None,
+        }
+    }
+
+    /// Creates a device error caused by another error, e.g. a failure from
+    /// the bluetooth or serialization layers.
+    pub fn with_source(message: &str, source: Box<dyn Error + Send + Sync + 'static>) -> Self {
+        Self {
+            message: message.to_owned(),
+            source: Some(Arc::from(source)),
         }
     }
 }
@@ -138,7 +178,10 @@ impl Error for ButtplugDeviceError {
     }
 
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+        match self.source {
+            Some(ref source) => Some(source.as_ref()),
+            None => None,
+        }
     }
 }
 
@@ -207,7 +250,13 @@ impl Error for ButtplugError {
     }
 
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+        match *self {
+            ButtplugError::ButtplugDeviceError(ref e) => e.source(),
+            ButtplugError::ButtplugMessageError(ref e) => e.source(),
+            ButtplugError::ButtplugPingError(ref e) => e.source(),
+            ButtplugError::ButtplugHandshakeError(ref e) => e.source(),
+            ButtplugError::ButtplugUnknownError(ref e) => e.source(),
+        }
     }
 }
 
@@ -241,10 +290,11 @@ impl From<messages::Error> for ButtplugError {
         match error.error_code {
             ErrorCode::ErrorDevice => ButtplugError::ButtplugDeviceError(ButtplugDeviceError {
                 message: error.error_message,
+                source: None,
             }),
-            ErrorCode::ErrorMessage => ButtplugError::ButtplugMessageError(ButtplugMessageError {
-                message: error.error_message,
-            }),
+            ErrorCode::ErrorMessage => ButtplugError::ButtplugMessageError(
+                ButtplugMessageError::Other(error.error_message),
+            ),
             ErrorCode::ErrorHandshake => {
                 ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError {
                     message: error.error_message,
@@ -259,3 +309,25 @@ impl From<messages::Error> for ButtplugError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ButtplugDeviceError, ButtplugError};
+    use std::error::Error;
+    use std::io;
+
+    #[test]
+    fn test_device_error_source() {
+        let inner = io::Error::new(io::ErrorKind::NotFound, "Test IO Error");
+        let error = ButtplugDeviceError::with_source("Device went away", Box::new(inner));
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "Test IO Error");
+        assert_eq!(
+            source.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+        let error: ButtplugError = error.into();
+        assert!(error.source().unwrap().is::<io::Error>());
+        assert!(ButtplugDeviceError::new("No source").source().is_none());
+    }
+}
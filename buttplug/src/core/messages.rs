@@ -9,39 +9,109 @@
 //! Protocol](https://buttplug-spec.docs.buttplug.io) messages
 
 use super::errors::*;
-#[cfg(feature = "serialize_json")]
+#[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "serialize_json")]
+#[cfg(feature = "serialize")]
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fmt,
+    iter::FromIterator,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 /// Base trait for all Buttplug Protocol Message Structs. Handles management of
 /// message ids, as well as implementing conveinence functions for converting
 /// between message structs and [ButtplugMessageUnion] enums, serialization, etc...
+///
+/// Id 0 is reserved for messages the server sends on its own (events, logs,
+/// etc...), so messages sent by clients default to id 1, and server messages
+/// default to id 0.
 pub trait ButtplugMessage: Send + Sync + Clone {
     /// Returns the id number of the message
     fn get_id(&self) -> u32;
     /// Sets the id number of the message
     fn set_id(&mut self, id: u32);
+    /// Returns a copy of the message with the given id, leaving the original
+    /// untouched.
+    fn clone_with_id(&self, id: u32) -> Self
+    where
+        Self: Sized,
+    {
+        let mut msg = self.clone();
+        msg.set_id(id);
+        msg
+    }
     /// Returns the message as a [ButtplugMessageUnion] enum.
     fn as_union(self) -> ButtplugMessageUnion;
     /// Returns the message as a string in Buttplug JSON Protocol format.
+    ///
+    /// Only available with the `serialize_json` feature.
     #[cfg(feature = "serialize_json")]
     fn as_protocol_json(self) -> String
     where
-        Self: ButtplugMessage + Serialize + Deserialize<'static>,
+        Self: Sized,
     {
-        "[".to_owned() + &serde_json::to_string(&self).unwrap() + "]"
+        serde_json::to_string(&ButtplugMessageArray(vec![self.as_union()])).unwrap()
+    }
+}
+
+/// Implemented by every message struct, but not by the message unions, which
+/// can hold any message type. Lets code that is generic over message types
+/// find out which message it is dealing with, and take it out of a
+/// [ButtplugMessageUnion].
+pub trait ButtplugMessageType:
+    ButtplugMessage + TryFrom<ButtplugMessageUnion, Error = ButtplugMessageUnion>
+{
+    /// Name of the message, as used in the Buttplug Protocol Spec.
+    const MESSAGE_NAME: &'static str;
+}
+
+/// Hands out unique ids for outgoing messages, so replies can be matched to
+/// the message that caused them. Id 0 is reserved for messages originating
+/// from the server (events, logs, etc...), so it is never generated.
+#[derive(Debug)]
+pub struct MessageIdGenerator {
+    next_id: AtomicU32,
+}
+
+impl MessageIdGenerator {
+    /// Returns the next message id, skipping 0 if the counter wraps.
+    pub fn next_id(&self) -> u32 {
+        loop {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            if id != 0 {
+                return id;
+            }
+        }
+    }
+
+    /// Sets the id of the message to the next message id, and returns that
+    /// id.
+    pub fn stamp(&self, msg: &mut impl ButtplugMessage) -> u32 {
+        let id = self.next_id();
+        msg.set_id(id);
+        id
+    }
+}
+
+impl Default for MessageIdGenerator {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU32::new(1),
+        }
     }
 }
 
 /// Represents the Buttplug Protocol Ok message, as documented in the [Buttplug
 /// Protocol Spec](https://buttplug-spec.docs.buttplug.io/status.html#ok).
 #[derive(Debug, PartialEq, Default, ButtplugMessage, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct Ok {
     /// Message Id, used for matching message pairs in remote connection instances.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
 }
 
@@ -55,7 +125,7 @@ impl Ok {
 /// Error codes pertaining to error classes that can be represented in the
 /// Buttplug [Error] message.
 #[derive(Debug, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize_repr, Deserialize_repr))]
+#[cfg_attr(feature = "serialize", derive(Serialize_repr, Deserialize_repr))]
 #[repr(u8)]
 pub enum ErrorCode {
     ErrorUnknown = 0,
@@ -68,16 +138,17 @@ pub enum ErrorCode {
 /// Represents the Buttplug Protocol Error message, as documented in the [Buttplug
 /// Protocol Spec](https://buttplug-spec.docs.buttplug.io/status.html#error).
 #[derive(Debug, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct Error {
     /// Message Id, used for matching message pairs in remote connection instances.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
     /// Specifies the class of the error.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "ErrorCode"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "ErrorCode"))]
     pub error_code: ErrorCode,
     /// Description of the error.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "ErrorMessage"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "ErrorMessage"))]
     pub error_message: String,
 }
 
@@ -106,7 +177,7 @@ impl From<ButtplugError> for Error {
         // Gross but was having problems with naming collisions on the error trait
         let msg = match error {
             ButtplugError::ButtplugDeviceError(_s) => _s.message,
-            ButtplugError::ButtplugMessageError(_s) => _s.message,
+            ButtplugError::ButtplugMessageError(_s) => _s.message(),
             ButtplugError::ButtplugPingError(_s) => _s.message,
             ButtplugError::ButtplugHandshakeError(_s) => _s.message,
             ButtplugError::ButtplugUnknownError(_s) => _s.message,
@@ -116,10 +187,11 @@ impl From<ButtplugError> for Error {
 }
 
 #[derive(Debug, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct Ping {
     /// Message Id, used for matching message pairs in remote connection instances.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
 }
 
@@ -130,17 +202,27 @@ impl Default for Ping {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct Test {
     /// Message Id, used for matching message pairs in remote connection instances.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
     /// Test string, which will be echo'd back to client when sent to server.
-    #[cfg_attr(feature = "serialize_json", serde(rename = "TestString"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "TestString"))]
     test_string: String,
 }
 
+impl Default for Test {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            test_string: Default::default(),
+        }
+    }
+}
+
 impl Test {
     /// Creates a new Ping message with the given Id.
     pub fn new(test: &str) -> Self {
@@ -152,23 +234,60 @@ impl Test {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct MessageAttributes {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "FeatureCount"))]
+    #[cfg_attr(
+        feature = "serialize",
+        serde(rename = "FeatureCount", skip_serializing_if = "Option::is_none")
+    )]
     pub feature_count: Option<u32>,
+    /// Names of the stored patterns a device can play via [PatternCmd].
+    #[cfg_attr(
+        feature = "serialize",
+        serde(rename = "Patterns", default, skip_serializing_if = "Option::is_none")
+    )]
+    pub patterns: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct DeviceMessageInfo {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceName"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceName"))]
     pub device_name: String,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceMessages"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceMessages"))]
     pub device_messages: HashMap<String, MessageAttributes>,
 }
 
+impl DeviceMessageInfo {
+    /// Number of vibration motors, or None if the device doesn't support
+    /// [VibrateCmd].
+    pub fn vibrate_feature_count(&self) -> Option<u32> {
+        self.feature_count(VibrateCmd::MESSAGE_NAME)
+    }
+
+    /// Number of rotators, or None if the device doesn't support
+    /// [RotateCmd].
+    pub fn rotate_feature_count(&self) -> Option<u32> {
+        self.feature_count(RotateCmd::MESSAGE_NAME)
+    }
+
+    /// Number of linear actuators, or None if the device doesn't support
+    /// [LinearCmd].
+    pub fn linear_feature_count(&self) -> Option<u32> {
+        self.feature_count(LinearCmd::MESSAGE_NAME)
+    }
+
+    fn feature_count(&self, message_name: &str) -> Option<u32> {
+        self.device_messages
+            .get(message_name)
+            .and_then(|attributes| attributes.feature_count)
+    }
+}
+
 impl From<&DeviceAdded> for DeviceMessageInfo {
     fn from(device_added: &DeviceAdded) -> Self {
         Self {
@@ -180,40 +299,205 @@ impl From<&DeviceAdded> for DeviceMessageInfo {
 }
 
 #[derive(Default, ButtplugMessage, Clone, Debug, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct DeviceList {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Devices"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Devices"))]
     pub devices: Vec<DeviceMessageInfo>,
 }
 
+impl DeviceList {
+    /// Compares this device list against an earlier snapshot, keyed by device
+    /// index. A device whose index is in both lists but whose info changed is
+    /// reported as removed (old info) and added (new info).
+    pub fn diff(&self, previous: &DeviceList) -> DeviceListDiff {
+        let mut diff = DeviceListDiff::default();
+        for device in &self.devices {
+            match previous
+                .devices
+                .iter()
+                .find(|prev| prev.device_index == device.device_index)
+            {
+                Some(prev) if prev == device => diff.unchanged.push(device.clone()),
+                Some(prev) => {
+                    diff.removed.push(prev.clone());
+                    diff.added.push(device.clone());
+                }
+                None => diff.added.push(device.clone()),
+            }
+        }
+        for prev in &previous.devices {
+            if !self
+                .devices
+                .iter()
+                .any(|device| device.device_index == prev.device_index)
+            {
+                diff.removed.push(prev.clone());
+            }
+        }
+        diff
+    }
+}
+
+/// Changes between two [DeviceList] snapshots, as returned by
+/// [DeviceList::diff].
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct DeviceListDiff {
+    pub added: Vec<DeviceMessageInfo>,
+    pub removed: Vec<DeviceMessageInfo>,
+    pub unchanged: Vec<DeviceMessageInfo>,
+}
+
 #[derive(Default, ButtplugMessage, Clone, Debug, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct DeviceAdded {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceName"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceName"))]
     pub device_name: String,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceMessages"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceMessages"))]
     pub device_messages: HashMap<String, MessageAttributes>,
 }
 
+impl DeviceAdded {
+    pub fn new(
+        device_index: u32,
+        device_name: &str,
+        device_messages: HashMap<String, MessageAttributes>,
+    ) -> Self {
+        Self {
+            id: 0,
+            device_index,
+            device_name: device_name.to_owned(),
+            device_messages,
+        }
+    }
+}
+
+/// [DeviceMessageInfo] as sent to Version0 clients, which only get the
+/// names of the messages a device accepts.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct DeviceMessageInfoVersion0 {
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceName"))]
+    pub device_name: String,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceMessages"))]
+    pub device_messages: Vec<String>,
+}
+
+impl From<&DeviceMessageInfo> for DeviceMessageInfoVersion0 {
+    fn from(device: &DeviceMessageInfo) -> Self {
+        Self {
+            device_index: device.device_index,
+            device_name: device.device_name.clone(),
+            device_messages: version0_message_names(&device.device_messages),
+        }
+    }
+}
+
+/// [DeviceList] as sent to Version0 clients. Serializes as a DeviceList.
+#[derive(Default, ButtplugMessage, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct DeviceListVersion0 {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "Devices"))]
+    pub devices: Vec<DeviceMessageInfoVersion0>,
+}
+
+impl From<&DeviceList> for DeviceListVersion0 {
+    fn from(list: &DeviceList) -> Self {
+        Self {
+            id: list.id,
+            devices: list
+                .devices
+                .iter()
+                .map(DeviceMessageInfoVersion0::from)
+                .collect(),
+        }
+    }
+}
+
+/// [DeviceAdded] as sent to Version0 clients. Serializes as a DeviceAdded.
+#[derive(Default, ButtplugMessage, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct DeviceAddedVersion0 {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceName"))]
+    pub device_name: String,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceMessages"))]
+    pub device_messages: Vec<String>,
+}
+
+impl From<&DeviceAdded> for DeviceAddedVersion0 {
+    fn from(device_added: &DeviceAdded) -> Self {
+        Self {
+            id: device_added.id,
+            device_index: device_added.device_index,
+            device_name: device_added.device_name.clone(),
+            device_messages: version0_message_names(&device_added.device_messages),
+        }
+    }
+}
+
+/// Names of the Version0 messages a device accepts, sorted. Devices that
+/// take [VibrateCmd] are listed as taking [SingleMotorVibrateCmd], which the
+/// server translates.
+fn version0_message_names(device_messages: &HashMap<String, MessageAttributes>) -> Vec<String> {
+    let mut names: Vec<String> = device_messages
+        .keys()
+        .map(|name| {
+            if name == VibrateCmd::MESSAGE_NAME {
+                SingleMotorVibrateCmd::MESSAGE_NAME
+            } else {
+                name
+            }
+        })
+        .filter(|name| device_message_spec_version(name) == MessageSpecVersion::Version0)
+        .map(str::to_owned)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
 #[derive(Debug, Default, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct DeviceRemoved {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
 }
 
+impl DeviceRemoved {
+    pub fn new(device_index: u32) -> Self {
+        Self {
+            id: 0,
+            device_index,
+        }
+    }
+}
+
 #[derive(Debug, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct StartScanning {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
 }
 
@@ -224,9 +508,10 @@ impl Default for StartScanning {
 }
 
 #[derive(Debug, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct StopScanning {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
 }
 
@@ -237,16 +522,18 @@ impl Default for StopScanning {
 }
 
 #[derive(Debug, Default, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct ScanningFinished {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
 }
 
 #[derive(Debug, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct RequestDeviceList {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
 }
 
@@ -256,19 +543,70 @@ impl Default for RequestDeviceList {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+/// Versions of the Buttplug Message Spec. Serialized as the bare version
+/// integer, to stay wire compatible with other Buttplug implementations.
+/// Deserialized through [TryFrom<u32>], so unknown versions fail with a
+/// handshake error.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize_repr, Deserialize),
+    serde(try_from = "u32")
+)]
+#[repr(u32)]
+pub enum MessageSpecVersion {
+    #[default]
+    Version0 = 0,
+    Version1 = 1,
+    Version2 = 2,
+}
+
+impl TryFrom<u32> for MessageSpecVersion {
+    type Error = ButtplugHandshakeError;
+
+    fn try_from(version: u32) -> Result<Self, ButtplugHandshakeError> {
+        match version {
+            0 => Ok(MessageSpecVersion::Version0),
+            1 => Ok(MessageSpecVersion::Version1),
+            2 => Ok(MessageSpecVersion::Version2),
+            _ => Err(ButtplugHandshakeError::new(&format!(
+                "Unknown message spec version {}.",
+                version
+            ))),
+        }
+    }
+}
+
+impl From<MessageSpecVersion> for u32 {
+    fn from(version: MessageSpecVersion) -> Self {
+        version as u32
+    }
+}
+
+#[derive(Debug, ButtplugMessage, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct RequestServerInfo {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "ClientName"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "ClientName"))]
     pub client_name: String,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "MessageVersion"))]
-    pub message_version: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "MessageVersion"))]
+    pub message_version: MessageSpecVersion,
+}
+
+impl Default for RequestServerInfo {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            client_name: Default::default(),
+            message_version: Default::default(),
+        }
+    }
 }
 
 impl RequestServerInfo {
-    pub fn new(client_name: &str, message_version: u32) -> Self {
+    pub fn new(client_name: &str, message_version: MessageSpecVersion) -> Self {
         Self {
             id: 1,
             client_name: client_name.to_string(),
@@ -278,26 +616,27 @@ impl RequestServerInfo {
 }
 
 #[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct ServerInfo {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "MajorVersion"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "MajorVersion"))]
     pub major_version: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "MinorVersion"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "MinorVersion"))]
     pub minor_version: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "BuildVersion"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "BuildVersion"))]
     pub build_version: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "MessageVersion"))]
-    pub message_version: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "MaxPingTime"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "MessageVersion"))]
+    pub message_version: MessageSpecVersion,
+    #[cfg_attr(feature = "serialize", serde(rename = "MaxPingTime"))]
     pub max_ping_time: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "ServerName"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "ServerName"))]
     pub server_name: String,
 }
 
 impl ServerInfo {
-    pub fn new(server_name: &str, message_version: u32, max_ping_time: u32) -> Self {
+    pub fn new(server_name: &str, message_version: MessageSpecVersion, max_ping_time: u32) -> Self {
         Self {
             id: 0,
             major_version: 0,
@@ -310,8 +649,9 @@ impl ServerInfo {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+/// Log levels, ordered from least (Off) to most (Trace) verbose.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum LogLevel {
     Off = 0,
     Fatal,
@@ -323,11 +663,12 @@ pub enum LogLevel {
 }
 
 #[derive(Debug, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct RequestLog {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "LogLevel"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "LogLevel"))]
     pub log_level: LogLevel,
 }
 
@@ -338,13 +679,14 @@ impl RequestLog {
 }
 
 #[derive(Debug, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct Log {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "LogLevel"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "LogLevel"))]
     pub log_level: LogLevel,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "LogMessage"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "LogMessage"))]
     pub log_message: String,
 }
 
@@ -358,15 +700,25 @@ impl Log {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct StopDeviceCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
 }
 
+impl Default for StopDeviceCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+        }
+    }
+}
+
 impl StopDeviceCmd {
     pub fn new(device_index: u32) -> Self {
         Self {
@@ -376,19 +728,27 @@ impl StopDeviceCmd {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct StopAllDevices {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
 }
 
+impl Default for StopAllDevices {
+    fn default() -> Self {
+        Self { id: 1 }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct VibrateSubcommand {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Index"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Index"))]
     pub index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Speed"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Speed"))]
     pub speed: f64,
 }
 
@@ -398,17 +758,69 @@ impl VibrateSubcommand {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+/// Checks the (index, speed) pairs of a [VibrateCmd], [RotateCmd] or
+/// [OscillateCmd] against the message spec. There must be at least one, all
+/// speeds must be in the range 0.0-1.0, and each index may only be addressed
+/// once.
+fn check_speeds(
+    message_name: &str,
+    field: &str,
+    speeds: impl Iterator<Item = (u32, f64)>,
+) -> Result<(), ButtplugMessageError> {
+    let mut indexes = HashSet::new();
+    for (index, speed) in speeds {
+        if !(0.0..=1.0).contains(&speed) {
+            return Err(ButtplugMessageError::invalid_field(
+                field,
+                &format!(
+                    "{} speed {} for index {} is not in the range 0.0-1.0.",
+                    message_name, speed, index
+                ),
+            ));
+        }
+        if !indexes.insert(index) {
+            return Err(ButtplugMessageError::invalid_field(
+                field,
+                &format!(
+                    "{} contains multiple speeds for index {}.",
+                    message_name, index
+                ),
+            ));
+        }
+    }
+    if indexes.is_empty() {
+        return Err(ButtplugMessageError::invalid_field(
+            field,
+            &format!("{} requires at least one speed.", message_name),
+        ));
+    }
+    Ok(())
+}
+
+/// Sets vibration speeds. Only the motors listed in `speeds` change, any
+/// motor left out keeps running at its last speed.
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct VibrateCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Speeds"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Speeds"))]
     pub speeds: Vec<VibrateSubcommand>,
 }
 
+impl Default for VibrateCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            speeds: Default::default(),
+        }
+    }
+}
+
 impl VibrateCmd {
     pub fn new(device_index: u32, speeds: Vec<VibrateSubcommand>) -> Self {
         Self {
@@ -417,16 +829,87 @@ impl VibrateCmd {
             speeds,
         }
     }
+
+    /// Creates a command setting every motor on a device with `motor_count`
+    /// motors to the same speed.
+    pub fn new_uniform(device_index: u32, motor_count: u32, speed: f64) -> Self {
+        Self::new(
+            device_index,
+            (0..motor_count)
+                .map(|i| VibrateSubcommand::new(i, speed))
+                .collect(),
+        )
+    }
+
+    /// Checks the command against the message spec. There must be at least
+    /// one speed, all speeds must be in the range 0.0-1.0, and each motor
+    /// index may only be addressed once.
+    pub fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+        check_speeds(
+            Self::MESSAGE_NAME,
+            "Speeds",
+            self.speeds.iter().map(|speed| (speed.index, speed.speed)),
+        )
+    }
+}
+
+/// Oscillator speeds take the same form as vibration speeds.
+pub type OscillateSubcommand = VibrateSubcommand;
+
+/// Sets oscillation speeds, for devices that move back and forth instead of
+/// (or as well as) vibrating. Works the same as [VibrateCmd].
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct OscillateCmd {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    pub id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "Speeds"))]
+    pub speeds: Vec<OscillateSubcommand>,
+}
+
+impl Default for OscillateCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            speeds: Default::default(),
+        }
+    }
+}
+
+impl OscillateCmd {
+    pub fn new(device_index: u32, speeds: Vec<OscillateSubcommand>) -> Self {
+        Self {
+            id: 1,
+            device_index,
+            speeds,
+        }
+    }
+
+    /// Checks the command against the message spec. There must be at least
+    /// one speed, all speeds must be in the range 0.0-1.0, and each
+    /// oscillator index may only be addressed once.
+    pub fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+        check_speeds(
+            Self::MESSAGE_NAME,
+            "Speeds",
+            self.speeds.iter().map(|speed| (speed.index, speed.speed)),
+        )
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct VectorSubcommand {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Index"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Index"))]
     pub index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Duration"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Duration"))]
     pub duration: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Position"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Position"))]
     pub position: f64,
 }
 
@@ -440,17 +923,28 @@ impl VectorSubcommand {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct LinearCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Vectors"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Vectors"))]
     pub vectors: Vec<VectorSubcommand>,
 }
 
+impl Default for LinearCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            vectors: Default::default(),
+        }
+    }
+}
+
 impl LinearCmd {
     pub fn new(device_index: u32, vectors: Vec<VectorSubcommand>) -> Self {
         Self {
@@ -459,16 +953,44 @@ impl LinearCmd {
             vectors,
         }
     }
+
+    /// Checks the command against the message spec. All positions must be in
+    /// the range 0.0-1.0, and each actuator index may only be addressed once.
+    pub fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+        let mut indexes = HashSet::new();
+        for vector in &self.vectors {
+            if !(0.0..=1.0).contains(&vector.position) {
+                return Err(ButtplugMessageError::invalid_field(
+                    "Vectors",
+                    &format!(
+                        "LinearCmd position {} for index {} is not in the range 0.0-1.0.",
+                        vector.position, vector.index
+                    ),
+                ));
+            }
+            if !indexes.insert(vector.index) {
+                return Err(ButtplugMessageError::invalid_field(
+                    "Vectors",
+                    &format!(
+                        "LinearCmd contains multiple vectors for index {}.",
+                        vector.index
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct RotationSubcommand {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Index"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Index"))]
     pub index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Speed"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Speed"))]
     pub speed: f64,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Clockwise"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Clockwise"))]
     pub clockwise: bool,
 }
 
@@ -482,17 +1004,28 @@ impl RotationSubcommand {
     }
 }
 
-#[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct RotateCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Rotations"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Rotations"))]
     pub rotations: Vec<RotationSubcommand>,
 }
 
+impl Default for RotateCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            rotations: Default::default(),
+        }
+    }
+}
+
 impl RotateCmd {
     pub fn new(device_index: u32, rotations: Vec<RotationSubcommand>) -> Self {
         Self {
@@ -501,21 +1034,46 @@ impl RotateCmd {
             rotations,
         }
     }
+
+    /// Checks the command against the message spec. There must be at least
+    /// one rotation, all speeds must be in the range 0.0-1.0, and each
+    /// rotator index may only be addressed once.
+    pub fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+        check_speeds(
+            Self::MESSAGE_NAME,
+            "Rotations",
+            self.rotations
+                .iter()
+                .map(|rotation| (rotation.index, rotation.speed)),
+        )
+    }
 }
 
-#[derive(Debug, Default, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct FleshlightLaunchFW12Cmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Position"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Position"))]
     pub position: u8,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Speed"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Speed"))]
     pub speed: u8,
 }
 
+impl Default for FleshlightLaunchFW12Cmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            position: Default::default(),
+            speed: Default::default(),
+        }
+    }
+}
+
 impl FleshlightLaunchFW12Cmd {
     pub fn new(device_index: u32, position: u8, speed: u8) -> Self {
         Self {
@@ -525,16 +1083,41 @@ impl FleshlightLaunchFW12Cmd {
             speed,
         }
     }
+
+    /// Checks the command against the range the Launch firmware accepts.
+    /// Position and speed must both be 0-99.
+    pub fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+        if self.position > 99 {
+            return Err(ButtplugMessageError::invalid_field(
+                "Position",
+                &format!(
+                    "FleshlightLaunchFW12Cmd position {} is not in the range 0-99.",
+                    self.position
+                ),
+            ));
+        }
+        if self.speed > 99 {
+            return Err(ButtplugMessageError::invalid_field(
+                "Speed",
+                &format!(
+                    "FleshlightLaunchFW12Cmd speed {} is not in the range 0-99.",
+                    self.speed
+                ),
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct LovenseCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Command"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Command"))]
     pub command: String,
 }
 
@@ -550,13 +1133,14 @@ impl LovenseCmd {
 
 // Dear god this needs to be deprecated
 #[derive(Debug, ButtplugMessage, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct KiirooCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Command"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Command"))]
     pub command: String,
 }
 
@@ -570,19 +1154,67 @@ impl KiirooCmd {
     }
 }
 
-#[derive(Debug, ButtplugMessage, Default, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+/// Duration used for [LinearCmd] movements translated from [KiirooCmd], which
+/// has no concept of movement duration.
+pub const KIIROO_LINEAR_DURATION: u32 = 500;
+
+impl TryFrom<&KiirooCmd> for LinearCmd {
+    type Error = ButtplugMessageError;
+
+    /// Translates a legacy Onyx/Pearl [KiirooCmd] position command (0-4) into
+    /// a single vector [LinearCmd], using [KIIROO_LINEAR_DURATION] for the
+    /// movement. The message id is preserved so replies still match.
+    fn try_from(msg: &KiirooCmd) -> Result<Self, ButtplugMessageError> {
+        let position = match msg.command.trim().parse::<u32>() {
+            Ok(position) if position <= 4 => position,
+            _ => {
+                return Err(ButtplugMessageError::invalid_field(
+                    "Command",
+                    &format!(
+                        "KiirooCmd command \"{}\" is not a position between 0 and 4.",
+                        msg.command
+                    ),
+                ))
+            }
+        };
+        let mut linear_cmd = LinearCmd::new(
+            msg.device_index,
+            vec![VectorSubcommand::new(
+                0,
+                KIIROO_LINEAR_DURATION,
+                f64::from(position) / 4.0,
+            )],
+        );
+        linear_cmd.set_id(msg.get_id());
+        Ok(linear_cmd)
+    }
+}
+
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct VorzeA10CycloneCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Speed"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Speed"))]
     pub speed: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Clockwise"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Clockwise"))]
     pub clockwise: bool,
 }
 
+impl Default for VorzeA10CycloneCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            speed: Default::default(),
+            clockwise: Default::default(),
+        }
+    }
+}
+
 impl VorzeA10CycloneCmd {
     pub fn new(device_index: u32, speed: u32, clockwise: bool) -> Self {
         Self {
@@ -594,17 +1226,28 @@ impl VorzeA10CycloneCmd {
     }
 }
 
-#[derive(Debug, ButtplugMessage, Default, PartialEq, Clone)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
 pub struct SingleMotorVibrateCmd {
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Id"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
     pub id: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "DeviceIndex"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
     pub device_index: u32,
-    #[cfg_attr(feature = "serialize_json", serde(rename = "Speed"))]
+    #[cfg_attr(feature = "serialize", serde(rename = "Speed"))]
     pub speed: f64,
 }
 
+impl Default for SingleMotorVibrateCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            speed: Default::default(),
+        }
+    }
+}
+
 impl SingleMotorVibrateCmd {
     pub fn new(device_index: u32, speed: f64) -> Self {
         Self {
@@ -615,116 +1258,775 @@ impl SingleMotorVibrateCmd {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-#[cfg_attr(feature = "serialize_json", derive(Serialize, Deserialize))]
-pub enum ButtplugMessageUnion {
-    Ok(Ok),
-    Error(Error),
-    Ping(Ping),
-    Test(Test),
-    RequestLog(RequestLog),
-    Log(Log),
-    RequestServerInfo(RequestServerInfo),
-    ServerInfo(ServerInfo),
-    DeviceList(DeviceList),
-    DeviceAdded(DeviceAdded),
-    DeviceRemoved(DeviceRemoved),
-    StartScanning(StartScanning),
-    StopScanning(StopScanning),
-    ScanningFinished(ScanningFinished),
-    RequestDeviceList(RequestDeviceList),
-    VibrateCmd(VibrateCmd),
-    LinearCmd(LinearCmd),
-    RotateCmd(RotateCmd),
-    FleshlightLaunchFW12Cmd(FleshlightLaunchFW12Cmd),
-    LovenseCmd(LovenseCmd),
-    KiirooCmd(KiirooCmd),
-    VorzeA10CycloneCmd(VorzeA10CycloneCmd),
-    SingleMotorVibrateCmd(SingleMotorVibrateCmd),
-    StopDeviceCmd(StopDeviceCmd),
-    StopAllDevices(StopAllDevices),
-}
-
-impl ButtplugMessage for ButtplugMessageUnion {
-    fn get_id(&self) -> u32 {
-        match self {
-            ButtplugMessageUnion::Ok(ref msg) => msg.id,
-            ButtplugMessageUnion::Error(ref msg) => msg.id,
-            ButtplugMessageUnion::Log(ref msg) => msg.id,
-            ButtplugMessageUnion::RequestLog(ref msg) => msg.id,
-            ButtplugMessageUnion::Ping(ref msg) => msg.id,
-            ButtplugMessageUnion::Test(ref msg) => msg.id,
-            ButtplugMessageUnion::RequestServerInfo(ref msg) => msg.id,
-            ButtplugMessageUnion::ServerInfo(ref msg) => msg.id,
-            ButtplugMessageUnion::DeviceList(ref msg) => msg.id,
-            ButtplugMessageUnion::DeviceAdded(ref msg) => msg.id,
-            ButtplugMessageUnion::DeviceRemoved(ref msg) => msg.id,
-            ButtplugMessageUnion::StartScanning(ref msg) => msg.id,
-            ButtplugMessageUnion::StopScanning(ref msg) => msg.id,
-            ButtplugMessageUnion::ScanningFinished(ref msg) => msg.id,
-            ButtplugMessageUnion::RequestDeviceList(ref msg) => msg.id,
-            ButtplugMessageUnion::VibrateCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::LinearCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::RotateCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::FleshlightLaunchFW12Cmd(ref msg) => msg.id,
-            ButtplugMessageUnion::LovenseCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::KiirooCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::VorzeA10CycloneCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::SingleMotorVibrateCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::StopDeviceCmd(ref msg) => msg.id,
-            ButtplugMessageUnion::StopAllDevices(ref msg) => msg.id,
-        }
-    }
-
-    fn set_id(&mut self, id: u32) {
-        match self {
-            ButtplugMessageUnion::Ok(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::Error(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::Log(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::RequestLog(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::Ping(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::Test(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::RequestServerInfo(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::ServerInfo(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::DeviceList(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::DeviceAdded(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::DeviceRemoved(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::StartScanning(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::StopScanning(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::ScanningFinished(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::RequestDeviceList(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::VibrateCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::LinearCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::RotateCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::FleshlightLaunchFW12Cmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::LovenseCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::KiirooCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::VorzeA10CycloneCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::SingleMotorVibrateCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::StopDeviceCmd(ref mut msg) => msg.set_id(id),
-            ButtplugMessageUnion::StopAllDevices(ref mut msg) => msg.set_id(id),
-        }
-    }
-
-    fn as_union(self) -> ButtplugMessageUnion {
-        panic!("as_union shouldn't be called on union.");
+impl From<(&SingleMotorVibrateCmd, u32)> for VibrateCmd {
+    /// Upgrades a legacy [SingleMotorVibrateCmd] to a [VibrateCmd], given the
+    /// number of vibration motors on the device. Every motor is set to the
+    /// same speed, and the message id is preserved so replies still match.
+    fn from(msg_motor_tuple: (&SingleMotorVibrateCmd, u32)) -> Self {
+        let (msg, motor_count) = msg_motor_tuple;
+        let mut vibrate_cmd = VibrateCmd::new_uniform(msg.device_index, motor_count, msg.speed);
+        vibrate_cmd.set_id(msg.get_id());
+        vibrate_cmd
     }
 }
 
-#[cfg(feature = "serialize_json")]
-#[cfg(test)]
-mod test {
-    use super::{ButtplugMessageUnion, Error, ErrorCode, Ok};
-
-    const OK_STR: &str = "{\"Ok\":{\"Id\":0}}";
-    const ERROR_STR: &str =
-        "{\"Error\":{\"Id\":0,\"ErrorCode\":1,\"ErrorMessage\":\"Test Error\"}}";
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct BatteryLevelCmd {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    pub id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+}
 
-    #[test]
-    fn test_ok_serialize() {
-        let ok = ButtplugMessageUnion::Ok(Ok::new(0));
-        let js = serde_json::to_string(&ok).unwrap();
-        assert_eq!(OK_STR, js);
+impl Default for BatteryLevelCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+        }
+    }
+}
+
+impl BatteryLevelCmd {
+    pub fn new(device_index: u32) -> Self {
+        Self {
+            id: 1,
+            device_index,
+        }
+    }
+}
+
+#[derive(Debug, ButtplugMessage, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct BatteryLevelReading {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    pub id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+    /// Battery level, from 0.0 (empty) to 1.0 (full).
+    #[cfg_attr(feature = "serialize", serde(rename = "BatteryLevel"))]
+    pub battery_level: f64,
+}
+
+impl BatteryLevelReading {
+    /// Creates the reply to the [BatteryLevelCmd] with the given id.
+    pub fn new(id: u32, device_index: u32, battery_level: f64) -> Self {
+        Self {
+            id,
+            device_index,
+            battery_level,
+        }
+    }
+}
+
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct RSSILevelCmd {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    pub id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+}
+
+impl Default for RSSILevelCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+        }
+    }
+}
+
+impl RSSILevelCmd {
+    pub fn new(device_index: u32) -> Self {
+        Self {
+            id: 1,
+            device_index,
+        }
+    }
+}
+
+#[derive(Debug, ButtplugMessage, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct RSSILevelReading {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    pub id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+    /// Received signal strength, in dBm.
+    #[cfg_attr(feature = "serialize", serde(rename = "RSSILevel"))]
+    pub rssi_level: i32,
+}
+
+impl RSSILevelReading {
+    /// Creates the reply to the [RSSILevelCmd] with the given id.
+    pub fn new(id: u32, device_index: u32, rssi_level: i32) -> Self {
+        Self {
+            id,
+            device_index,
+            rssi_level,
+        }
+    }
+}
+
+/// Plays one of the stored patterns a device advertises in the
+/// [MessageAttributes] patterns list for PatternCmd.
+#[derive(Debug, ButtplugMessage, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strict_parsing", serde(deny_unknown_fields))]
+pub struct PatternCmd {
+    #[cfg_attr(feature = "serialize", serde(rename = "Id"))]
+    pub id: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "DeviceIndex"))]
+    pub device_index: u32,
+    #[cfg_attr(feature = "serialize", serde(rename = "PatternName"))]
+    pub pattern_name: String,
+    /// Actuator to play the pattern on. Plays on all actuators if None.
+    #[cfg_attr(
+        feature = "serialize",
+        serde(rename = "Index", default, skip_serializing_if = "Option::is_none")
+    )]
+    pub index: Option<u32>,
+}
+
+impl Default for PatternCmd {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            device_index: Default::default(),
+            pattern_name: Default::default(),
+            index: Default::default(),
+        }
+    }
+}
+
+impl PatternCmd {
+    pub fn new(device_index: u32, pattern_name: &str, index: Option<u32>) -> Self {
+        Self {
+            id: 1,
+            device_index,
+            pattern_name: pattern_name.to_owned(),
+            index,
+        }
+    }
+
+    /// Checks that the requested pattern is in the list of patterns the
+    /// device advertised.
+    pub fn is_valid_for(&self, attributes: &MessageAttributes) -> Result<(), ButtplugMessageError> {
+        match attributes.patterns {
+            Some(ref patterns) if patterns.contains(&self.pattern_name) => Result::Ok(()),
+            _ => Result::Err(ButtplugMessageError::invalid_field(
+                "PatternName",
+                &format!("Unknown pattern {}", self.pattern_name),
+            )),
+        }
+    }
+}
+
+/// Declares the message union and the client and server direction unions,
+/// along with [ButtplugMessageUnion::ALL_NAMES], [ButtplugMessageUnion::name],
+/// the union's [ButtplugMessage] implementation, and the conversions between
+/// the unions, so adding a message updates all of them. Each variant must be
+/// named after the message struct it holds.
+///
+/// Messages are listed by the direction they travel in: client to server,
+/// server to client, or both.
+///
+/// Stand-ins are older spec versions' forms of messages whose shape has
+/// changed, as produced by [ButtplugMessageUnion::downgrade_to]. They are
+/// server messages, serialized under the protocol name given for them, are
+/// never deserialized, and are left out of `ALL_NAMES`.
+macro_rules! message_union {
+    (@count $name:ident) => {
+        1
+    };
+    (
+        $(#[$attr:meta])*
+        pub enum $union:ident {
+            $(#[$client_attr:meta])*
+            client $client_union:ident {
+                $($client:ident),* $(,)?
+            }
+            $(#[$server_attr:meta])*
+            server $server_union:ident {
+                $($server:ident),* $(,)?
+            }
+            both {
+                $($both:ident),* $(,)?
+            }
+            stand_ins {
+                $($stand_in:ident => $stand_in_name:literal),* $(,)?
+            }
+        }
+    ) => {
+        $(#[$attr])*
+        pub enum $union {
+            $($client($client),)*
+            $($server($server),)*
+            $($both($both),)*
+            $(
+                #[cfg_attr(
+                    feature = "serialize",
+                    serde(rename = $stand_in_name, skip_deserializing)
+                )]
+                $stand_in($stand_in),
+            )*
+        }
+
+        $(#[$attr])*
+        $(#[$client_attr])*
+        pub enum $client_union {
+            $($client($client),)*
+            $($both($both),)*
+        }
+
+        $(#[$attr])*
+        $(#[$server_attr])*
+        pub enum $server_union {
+            $($server($server),)*
+            $($both($both),)*
+            $(
+                #[cfg_attr(
+                    feature = "serialize",
+                    serde(rename = $stand_in_name, skip_deserializing)
+                )]
+                $stand_in($stand_in),
+            )*
+        }
+
+        impl $union {
+            /// Protocol names of every message type in the union, in
+            /// declaration order.
+            pub const ALL_NAMES: &'static [&'static str] = &[
+                $($client::MESSAGE_NAME,)*
+                $($server::MESSAGE_NAME,)*
+                $($both::MESSAGE_NAME,)*
+            ];
+
+            /// Number of message types declared in the union, not counting
+            /// stand-ins.
+            #[cfg(test)]
+            const VARIANT_COUNT: usize = 0
+                $(+ message_union!(@count $client))*
+                $(+ message_union!(@count $server))*
+                $(+ message_union!(@count $both))*;
+
+            /// Returns the protocol name of the message type.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($union::$client(_) => $client::MESSAGE_NAME,)*
+                    $($union::$server(_) => $server::MESSAGE_NAME,)*
+                    $($union::$both(_) => $both::MESSAGE_NAME,)*
+                    $($union::$stand_in(_) => $stand_in_name,)*
+                }
+            }
+
+            /// True if the message can be sent from a client to a server.
+            pub fn is_client_message(&self) -> bool {
+                match self {
+                    $($union::$client(_) => true,)*
+                    $($union::$both(_) => true,)*
+                    $($union::$server(_) => false,)*
+                    $($union::$stand_in(_) => false,)*
+                }
+            }
+
+            /// True if the message can be sent from a server to a client.
+            pub fn is_server_message(&self) -> bool {
+                match self {
+                    $($union::$server(_) => true,)*
+                    $($union::$both(_) => true,)*
+                    $($union::$stand_in(_) => true,)*
+                    $($union::$client(_) => false,)*
+                }
+            }
+        }
+
+        impl ButtplugMessage for $union {
+            fn get_id(&self) -> u32 {
+                match self {
+                    $($union::$client(ref msg) => msg.get_id(),)*
+                    $($union::$server(ref msg) => msg.get_id(),)*
+                    $($union::$both(ref msg) => msg.get_id(),)*
+                    $($union::$stand_in(ref msg) => msg.get_id(),)*
+                }
+            }
+
+            fn set_id(&mut self, id: u32) {
+                match self {
+                    $($union::$client(ref mut msg) => msg.set_id(id),)*
+                    $($union::$server(ref mut msg) => msg.set_id(id),)*
+                    $($union::$both(ref mut msg) => msg.set_id(id),)*
+                    $($union::$stand_in(ref mut msg) => msg.set_id(id),)*
+                }
+            }
+
+            fn as_union(self) -> ButtplugMessageUnion {
+                self
+            }
+        }
+
+        impl From<$client_union> for $union {
+            fn from(msg: $client_union) -> Self {
+                match msg {
+                    $($client_union::$client(msg) => $union::$client(msg),)*
+                    $($client_union::$both(msg) => $union::$both(msg),)*
+                }
+            }
+        }
+
+        impl TryFrom<$union> for $client_union {
+            type Error = ButtplugMessageError;
+
+            fn try_from(msg: $union) -> Result<Self, ButtplugMessageError> {
+                let name = msg.name();
+                match msg {
+                    $($union::$client(msg) => Ok($client_union::$client(msg)),)*
+                    $($union::$both(msg) => Ok($client_union::$both(msg)),)*
+                    $($union::$server(_))|*
+                    $(| $union::$stand_in(_))* => {
+                        Err(ButtplugMessageError::UnexpectedType(format!(
+                            "{} cannot be sent from client to server.",
+                            name
+                        )))
+                    }
+                }
+            }
+        }
+
+        impl From<$server_union> for $union {
+            fn from(msg: $server_union) -> Self {
+                match msg {
+                    $($server_union::$server(msg) => $union::$server(msg),)*
+                    $($server_union::$both(msg) => $union::$both(msg),)*
+                    $($server_union::$stand_in(msg) => $union::$stand_in(msg),)*
+                }
+            }
+        }
+
+        impl TryFrom<$union> for $server_union {
+            type Error = ButtplugMessageError;
+
+            fn try_from(msg: $union) -> Result<Self, ButtplugMessageError> {
+                let name = msg.name();
+                match msg {
+                    $($union::$server(msg) => Ok($server_union::$server(msg)),)*
+                    $($union::$both(msg) => Ok($server_union::$both(msg)),)*
+                    $($union::$stand_in(msg) => Ok($server_union::$stand_in(msg)),)*
+                    $($union::$client(_))|* => {
+                        Err(ButtplugMessageError::UnexpectedType(format!(
+                            "{} cannot be sent from server to client.",
+                            name
+                        )))
+                    }
+                }
+            }
+        }
+    };
+}
+
+message_union! {
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+    pub enum ButtplugMessageUnion {
+        /// Messages that can be sent from a client to a server.
+        client ButtplugClientMessageUnion {
+            Ping,
+            RequestLog,
+            RequestServerInfo,
+            StartScanning,
+            StopScanning,
+            RequestDeviceList,
+            VibrateCmd,
+            LinearCmd,
+            RotateCmd,
+            FleshlightLaunchFW12Cmd,
+            LovenseCmd,
+            KiirooCmd,
+            VorzeA10CycloneCmd,
+            SingleMotorVibrateCmd,
+            StopDeviceCmd,
+            StopAllDevices,
+            BatteryLevelCmd,
+            RSSILevelCmd,
+            PatternCmd,
+            OscillateCmd,
+        }
+        /// Messages that can be sent from a server to a client.
+        server ButtplugServerMessageUnion {
+            Ok,
+            Error,
+            Log,
+            ServerInfo,
+            DeviceList,
+            DeviceAdded,
+            DeviceRemoved,
+            ScanningFinished,
+            BatteryLevelReading,
+            RSSILevelReading,
+        }
+        both {
+            Test,
+        }
+        stand_ins {
+            DeviceListVersion0 => "DeviceList",
+            DeviceAddedVersion0 => "DeviceAdded",
+        }
+    }
+}
+
+/// A list of messages, as sent in a single Buttplug Protocol frame.
+/// Serializes to (and from) the JSON array the protocol wraps all messages
+/// in.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(transparent))]
+pub struct ButtplugMessageArray(pub Vec<ButtplugMessageUnion>);
+
+impl From<Vec<ButtplugMessageUnion>> for ButtplugMessageArray {
+    fn from(messages: Vec<ButtplugMessageUnion>) -> Self {
+        ButtplugMessageArray(messages)
+    }
+}
+
+impl FromIterator<ButtplugMessageUnion> for ButtplugMessageArray {
+    fn from_iter<I: IntoIterator<Item = ButtplugMessageUnion>>(iter: I) -> Self {
+        ButtplugMessageArray(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for ButtplugMessageArray {
+    type Item = ButtplugMessageUnion;
+    type IntoIter = std::vec::IntoIter<ButtplugMessageUnion>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for ButtplugMessageUnion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(id={}", self.name(), self.get_id())?;
+        if let Some(device_index) = self.device_index() {
+            write!(f, ", device={}", device_index)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl ButtplugMessageUnion {
+    /// Parses a Buttplug JSON Protocol frame (a JSON array of messages, as
+    /// produced by [ButtplugMessage::as_protocol_json]) into message unions.
+    ///
+    /// Returns a [ButtplugMessageError] if the frame is not valid JSON, is not
+    /// an array, is empty, or contains a message that cannot be parsed, and a
+    /// [ButtplugHandshakeError] if it contains a [RequestServerInfo] with an
+    /// unknown message spec version.
+    ///
+    /// Only available with the `serialize_json` feature.
+    #[cfg(feature = "serialize_json")]
+    pub fn try_from_json(msg_str: &str) -> Result<Vec<ButtplugMessageUnion>, ButtplugError> {
+        let value: serde_json::Value = serde_json::from_str(msg_str).map_err(|e| {
+            ButtplugMessageError::DeserializationFailed(format!("Malformed message JSON: {}", e))
+        })?;
+        let array = match value {
+            serde_json::Value::Array(array) => array,
+            _ => {
+                return Err(ButtplugMessageError::DeserializationFailed(
+                    "Buttplug messages must be wrapped in a JSON array.".to_owned(),
+                )
+                .into())
+            }
+        };
+        if array.is_empty() {
+            return Err(ButtplugMessageError::DeserializationFailed(
+                "Message array is empty.".to_owned(),
+            )
+            .into());
+        }
+        array
+            .into_iter()
+            .map(|msg_value| {
+                let msg_name = match msg_value {
+                    serde_json::Value::Object(ref obj) if obj.len() == 1 => {
+                        obj.keys().next().unwrap().clone()
+                    }
+                    _ => {
+                        return Err(ButtplugMessageError::DeserializationFailed(format!(
+                            "Expected an object with a single message type key, got {}",
+                            msg_value
+                        ))
+                        .into())
+                    }
+                };
+                if !ButtplugMessageUnion::ALL_NAMES.contains(&msg_name.as_str()) {
+                    return Err(ButtplugMessageError::UnexpectedType(format!(
+                        "Unknown message type: {}",
+                        msg_name
+                    ))
+                    .into());
+                }
+                // Clients newer than this library send versions it doesn't
+                // know, which the server has to refuse as a handshake error.
+                if let Some(version) =
+                    msg_value[RequestServerInfo::MESSAGE_NAME]["MessageVersion"].as_u64()
+                {
+                    MessageSpecVersion::try_from(u32::try_from(version).unwrap_or(u32::MAX))?;
+                }
+                serde_json::from_value(msg_value).map_err(|e| {
+                    ButtplugMessageError::DeserializationFailed(format!(
+                        "Invalid {} message: {}",
+                        msg_name, e
+                    ))
+                    .into()
+                })
+            })
+            .collect()
+    }
+
+    /// Encodes the message in CBOR.
+    #[cfg(feature = "serialize_cbor")]
+    pub fn to_cbor(&self) -> Vec<u8> {
+        serde_cbor::to_vec(self).unwrap()
+    }
+
+    /// Decodes a single CBOR encoded message, as produced by
+    /// [ButtplugMessageUnion::to_cbor].
+    #[cfg(feature = "serialize_cbor")]
+    pub fn try_from_cbor(data: &[u8]) -> Result<ButtplugMessageUnion, ButtplugMessageError> {
+        serde_cbor::from_slice(data).map_err(|e| {
+            ButtplugMessageError::DeserializationFailed(format!("Malformed message CBOR: {}", e))
+        })
+    }
+
+    /// Encodes the message in MessagePack. Structs are encoded as maps with
+    /// field names, since optional fields are skipped when empty.
+    #[cfg(feature = "serialize_msgpack")]
+    pub fn to_msgpack(&self) -> Vec<u8> {
+        rmp_serde::to_vec_named(self).unwrap()
+    }
+
+    /// Decodes a single MessagePack encoded message, as produced by
+    /// [ButtplugMessageUnion::to_msgpack].
+    #[cfg(feature = "serialize_msgpack")]
+    pub fn try_from_msgpack(data: &[u8]) -> Result<ButtplugMessageUnion, ButtplugMessageError> {
+        rmp_serde::from_read_ref(data).map_err(|e| {
+            ButtplugMessageError::DeserializationFailed(format!(
+                "Malformed message MessagePack: {}",
+                e
+            ))
+        })
+    }
+
+    /// Returns true if the message has the given id.
+    pub fn matches_id(&self, id: u32) -> bool {
+        self.get_id() == id
+    }
+
+    /// Returns true if this message could be the reply to the given request:
+    /// the ids match, and the message type is one the request can be answered
+    /// with. Any request can be answered with an [Error]. Messages with id 0
+    /// are server events, and never replies.
+    pub fn is_response_to(&self, request: &ButtplugMessageUnion) -> bool {
+        if self.get_id() == 0 || !self.matches_id(request.get_id()) {
+            return false;
+        }
+        match (request, self) {
+            (_, ButtplugMessageUnion::Error(_)) => true,
+            (ButtplugMessageUnion::RequestServerInfo(_), ButtplugMessageUnion::ServerInfo(_)) => {
+                true
+            }
+            (ButtplugMessageUnion::RequestDeviceList(_), ButtplugMessageUnion::DeviceList(_))
+            | (
+                ButtplugMessageUnion::RequestDeviceList(_),
+                ButtplugMessageUnion::DeviceListVersion0(_),
+            ) => true,
+            (ButtplugMessageUnion::Test(_), ButtplugMessageUnion::Test(_)) => true,
+            (
+                ButtplugMessageUnion::BatteryLevelCmd(_),
+                ButtplugMessageUnion::BatteryLevelReading(_),
+            ) => true,
+            (ButtplugMessageUnion::RSSILevelCmd(_), ButtplugMessageUnion::RSSILevelReading(_)) => {
+                true
+            }
+            (ButtplugMessageUnion::RequestServerInfo(_), _)
+            | (ButtplugMessageUnion::RequestDeviceList(_), _)
+            | (ButtplugMessageUnion::Test(_), _)
+            | (ButtplugMessageUnion::BatteryLevelCmd(_), _)
+            | (ButtplugMessageUnion::RSSILevelCmd(_), _) => false,
+            (_, ButtplugMessageUnion::Ok(_)) => request.is_client_message(),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the message is a system message (handshake, status,
+    /// scanning, logging, device list updates, etc...), false if it is
+    /// addressed to or sent from a specific device.
+    pub fn is_system_message(&self) -> bool {
+        self.device_index().is_none()
+    }
+
+    /// Returns the index of the device a device message is addressed to (or
+    /// sent from, for readings). Returns None for system messages.
+    ///
+    /// [DeviceAdded] and [DeviceRemoved] carry a device index, but are
+    /// system level notifications, so they also return None.
+    pub fn device_index(&self) -> Option<u32> {
+        match self {
+            ButtplugMessageUnion::VibrateCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::LinearCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::RotateCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::FleshlightLaunchFW12Cmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::LovenseCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::KiirooCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::VorzeA10CycloneCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::SingleMotorVibrateCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::StopDeviceCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::BatteryLevelCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::BatteryLevelReading(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::RSSILevelCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::RSSILevelReading(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::PatternCmd(ref msg) => Some(msg.device_index),
+            ButtplugMessageUnion::OscillateCmd(ref msg) => Some(msg.device_index),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of the message that can be understood by a client
+    /// speaking the given message spec version.
+    ///
+    /// Device messages added in later spec versions are removed from device
+    /// lists. For Version0, [DeviceAdded] and [DeviceList] become
+    /// [DeviceAddedVersion0] and [DeviceListVersion0], which list device
+    /// messages by name only, and [VibrateCmd] is converted to
+    /// [SingleMotorVibrateCmd]. Messages that need no changes are returned as
+    /// is.
+    pub fn downgrade_to(&self, version: MessageSpecVersion) -> ButtplugMessageUnion {
+        match self {
+            ButtplugMessageUnion::DeviceAdded(ref msg)
+                if version == MessageSpecVersion::Version0 =>
+            {
+                ButtplugMessageUnion::DeviceAddedVersion0(DeviceAddedVersion0::from(msg))
+            }
+            ButtplugMessageUnion::DeviceList(ref msg)
+                if version == MessageSpecVersion::Version0 =>
+            {
+                ButtplugMessageUnion::DeviceListVersion0(DeviceListVersion0::from(msg))
+            }
+            ButtplugMessageUnion::DeviceAdded(ref msg) => {
+                let mut msg = msg.clone();
+                msg.device_messages = downgrade_device_messages(&msg.device_messages, version);
+                ButtplugMessageUnion::DeviceAdded(msg)
+            }
+            ButtplugMessageUnion::DeviceList(ref msg) => {
+                let mut msg = msg.clone();
+                for device in msg.devices.iter_mut() {
+                    device.device_messages =
+                        downgrade_device_messages(&device.device_messages, version);
+                }
+                ButtplugMessageUnion::DeviceList(msg)
+            }
+            ButtplugMessageUnion::VibrateCmd(ref msg)
+                if version == MessageSpecVersion::Version0 =>
+            {
+                // Spec v0 devices only have a single speed, so use the
+                // strongest requested one.
+                let speed = msg.speeds.iter().map(|cmd| cmd.speed).fold(0.0, f64::max);
+                let mut single = SingleMotorVibrateCmd::new(msg.device_index, speed);
+                single.set_id(msg.get_id());
+                ButtplugMessageUnion::SingleMotorVibrateCmd(single)
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// Serializes a batch of messages into a single Buttplug JSON Protocol frame,
+/// preserving message order and ids.
+#[cfg(feature = "serialize_json")]
+pub fn serialize_batch(messages: &[ButtplugMessageUnion]) -> String {
+    serde_json::to_string(messages).unwrap()
+}
+
+/// Deserializes a Buttplug JSON Protocol frame containing one or more
+/// messages. See [ButtplugMessageUnion::try_from_json].
+#[cfg(feature = "serialize_json")]
+pub fn deserialize_batch(msg_str: &str) -> Result<Vec<ButtplugMessageUnion>, ButtplugError> {
+    ButtplugMessageUnion::try_from_json(msg_str)
+}
+
+/// Returns the message spec version that introduced the device message with
+/// the given name.
+fn device_message_spec_version(message_name: &str) -> MessageSpecVersion {
+    match message_name {
+        "VibrateCmd" | "LinearCmd" | "RotateCmd" => MessageSpecVersion::Version1,
+        "BatteryLevelCmd" | "RSSILevelCmd" | "PatternCmd" | "OscillateCmd" => {
+            MessageSpecVersion::Version2
+        }
+        _ => MessageSpecVersion::Version0,
+    }
+}
+
+fn downgrade_device_messages(
+    device_messages: &HashMap<String, MessageAttributes>,
+    version: MessageSpecVersion,
+) -> HashMap<String, MessageAttributes> {
+    device_messages
+        .iter()
+        .filter(|(name, _)| device_message_spec_version(name) <= version)
+        .map(|(name, attributes)| (name.clone(), attributes.clone()))
+        .collect()
+}
+
+#[cfg(feature = "serialize_json")]
+#[cfg(test)]
+mod test {
+    use super::{
+        deserialize_batch, serialize_batch, BatteryLevelCmd, BatteryLevelReading,
+        ButtplugClientMessageUnion, ButtplugError, ButtplugMessage, ButtplugMessageArray,
+        ButtplugMessageError, ButtplugMessageType, ButtplugMessageUnion,
+        ButtplugServerMessageUnion, DeviceAdded, DeviceList, DeviceMessageInfo, DeviceRemoved,
+        Error, ErrorCode, FleshlightLaunchFW12Cmd, KiirooCmd, LinearCmd, LogLevel,
+        MessageAttributes, MessageIdGenerator, MessageSpecVersion, Ok, OscillateCmd,
+        OscillateSubcommand, PatternCmd, Ping, RSSILevelCmd, RSSILevelReading, RequestDeviceList,
+        RequestServerInfo, RotateCmd, RotationSubcommand, ScanningFinished, ServerInfo,
+        SingleMotorVibrateCmd, StartScanning, StopAllDevices, StopDeviceCmd, StopScanning, Test,
+        VectorSubcommand, VibrateCmd, VibrateSubcommand, VorzeA10CycloneCmd,
+        KIIROO_LINEAR_DURATION,
+    };
+    use async_std::task;
+    use std::{
+        collections::{HashMap, HashSet},
+        convert::TryFrom,
+        sync::{atomic::AtomicU32, Arc},
+    };
+
+    const OK_STR: &str = "{\"Ok\":{\"Id\":0}}";
+    const ERROR_STR: &str =
+        "{\"Error\":{\"Id\":0,\"ErrorCode\":1,\"ErrorMessage\":\"Test Error\"}}";
+    const REQUEST_SERVER_INFO_STR: &str =
+        "{\"RequestServerInfo\":{\"Id\":1,\"ClientName\":\"Test Client\",\"MessageVersion\":1}}";
+    const BATTERY_LEVEL_CMD_STR: &str = "{\"BatteryLevelCmd\":{\"Id\":1,\"DeviceIndex\":3}}";
+    const BATTERY_LEVEL_READING_STR: &str =
+        "{\"BatteryLevelReading\":{\"Id\":1,\"DeviceIndex\":3,\"BatteryLevel\":0.5}}";
+    const RSSI_LEVEL_CMD_STR: &str = "{\"RSSILevelCmd\":{\"Id\":1,\"DeviceIndex\":3}}";
+    const OSCILLATE_CMD_STR: &str =
+        "{\"OscillateCmd\":{\"Id\":1,\"DeviceIndex\":2,\"Speeds\":[{\"Index\":0,\"Speed\":0.5}]}}";
+    const PATTERN_CMD_STR: &str =
+        "{\"PatternCmd\":{\"Id\":1,\"DeviceIndex\":2,\"PatternName\":\"Wave\"}}";
+    const RSSI_LEVEL_READING_STR: &str =
+        "{\"RSSILevelReading\":{\"Id\":1,\"DeviceIndex\":3,\"RSSILevel\":-40}}";
+
+    #[test]
+    fn test_ok_serialize() {
+        let ok = ButtplugMessageUnion::Ok(Ok::new(0));
+        let js = serde_json::to_string(&ok).unwrap();
+        assert_eq!(OK_STR, js);
     }
 
     #[test]
@@ -749,4 +2051,1243 @@ mod test {
             union
         );
     }
+
+    #[test]
+    fn test_battery_level_cmd_serialize() {
+        let msg = ButtplugMessageUnion::BatteryLevelCmd(BatteryLevelCmd::new(3));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(BATTERY_LEVEL_CMD_STR, js);
+    }
+
+    #[test]
+    fn test_battery_level_cmd_deserialize() {
+        let union: ButtplugMessageUnion = serde_json::from_str(BATTERY_LEVEL_CMD_STR).unwrap();
+        assert_eq!(
+            ButtplugMessageUnion::BatteryLevelCmd(BatteryLevelCmd::new(3)),
+            union
+        );
+    }
+
+    #[test]
+    fn test_battery_level_reading_serialize() {
+        let msg = ButtplugMessageUnion::BatteryLevelReading(BatteryLevelReading::new(1, 3, 0.5));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(BATTERY_LEVEL_READING_STR, js);
+    }
+
+    #[test]
+    fn test_battery_level_reading_deserialize() {
+        let union: ButtplugMessageUnion = serde_json::from_str(BATTERY_LEVEL_READING_STR).unwrap();
+        assert_eq!(
+            ButtplugMessageUnion::BatteryLevelReading(BatteryLevelReading::new(1, 3, 0.5)),
+            union
+        );
+    }
+
+    #[test]
+    fn test_rssi_level_cmd_serialize() {
+        let msg = ButtplugMessageUnion::RSSILevelCmd(RSSILevelCmd::new(3));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(RSSI_LEVEL_CMD_STR, js);
+    }
+
+    #[test]
+    fn test_rssi_level_cmd_deserialize() {
+        let union: ButtplugMessageUnion = serde_json::from_str(RSSI_LEVEL_CMD_STR).unwrap();
+        assert_eq!(
+            ButtplugMessageUnion::RSSILevelCmd(RSSILevelCmd::new(3)),
+            union
+        );
+    }
+
+    #[test]
+    fn test_rssi_level_reading_serialize() {
+        let msg = ButtplugMessageUnion::RSSILevelReading(RSSILevelReading::new(1, 3, -40));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(RSSI_LEVEL_READING_STR, js);
+    }
+
+    #[test]
+    fn test_rssi_level_reading_deserialize() {
+        let union: ButtplugMessageUnion = serde_json::from_str(RSSI_LEVEL_READING_STR).unwrap();
+        assert_eq!(
+            ButtplugMessageUnion::RSSILevelReading(RSSILevelReading::new(1, 3, -40)),
+            union
+        );
+    }
+
+    #[test]
+    fn test_request_server_info_serialize() {
+        let msg = ButtplugMessageUnion::RequestServerInfo(RequestServerInfo::new(
+            "Test Client",
+            MessageSpecVersion::Version1,
+        ));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(REQUEST_SERVER_INFO_STR, js);
+    }
+
+    #[test]
+    fn test_request_server_info_deserialize() {
+        let union: ButtplugMessageUnion = serde_json::from_str(REQUEST_SERVER_INFO_STR).unwrap();
+        assert_eq!(
+            ButtplugMessageUnion::RequestServerInfo(RequestServerInfo::new(
+                "Test Client",
+                MessageSpecVersion::Version1
+            )),
+            union
+        );
+    }
+
+    #[test]
+    fn test_message_spec_version_from_u32() {
+        assert_eq!(
+            MessageSpecVersion::try_from(1).unwrap(),
+            MessageSpecVersion::Version1
+        );
+        assert_eq!(u32::from(MessageSpecVersion::Version2), 2);
+        assert!(MessageSpecVersion::try_from(100).is_err());
+    }
+
+    #[test]
+    fn test_request_server_info_unknown_version() {
+        let err = ButtplugMessageUnion::try_from_json(
+            r#"[{"RequestServerInfo":{"Id":1,"ClientName":"Test Client","MessageVersion":3}}]"#,
+        )
+        .unwrap_err();
+        let err = Error::from(err);
+        assert_eq!(err.error_code, ErrorCode::ErrorHandshake);
+        assert!(err.error_message.contains("Unknown message spec version 3"));
+        assert!(serde_json::from_str::<MessageSpecVersion>("3").is_err());
+        assert_eq!(
+            serde_json::from_str::<MessageSpecVersion>("1").unwrap(),
+            MessageSpecVersion::Version1
+        );
+    }
+
+    fn downgrade_test_device_messages() -> HashMap<String, MessageAttributes> {
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "SingleMotorVibrateCmd".to_owned(),
+            MessageAttributes {
+                feature_count: None,
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "VibrateCmd".to_owned(),
+            MessageAttributes {
+                feature_count: Some(2),
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "BatteryLevelCmd".to_owned(),
+            MessageAttributes {
+                feature_count: None,
+                patterns: None,
+            },
+        );
+        device_messages
+    }
+
+    #[test]
+    fn test_downgrade_device_list_v1() {
+        let msg = ButtplugMessageUnion::DeviceList(DeviceList {
+            id: 1,
+            devices: vec![DeviceMessageInfo {
+                device_index: 0,
+                device_name: "Test Device".to_owned(),
+                device_messages: downgrade_test_device_messages(),
+            }],
+        });
+        let js = serde_json::to_string(&msg.downgrade_to(MessageSpecVersion::Version1)).unwrap();
+        let mut expected = downgrade_test_device_messages();
+        expected.remove("BatteryLevelCmd");
+        match serde_json::from_str(&js).unwrap() {
+            ButtplugMessageUnion::DeviceList(list) => {
+                assert_eq!(list.get_id(), 1);
+                assert_eq!(list.devices[0].device_messages, expected);
+            }
+            _ => panic!("Downgraded DeviceList should still be a DeviceList"),
+        }
+        // Nothing to strip for the current spec version.
+        assert_eq!(msg.downgrade_to(MessageSpecVersion::Version2), msg);
+    }
+
+    #[test]
+    fn test_downgrade_device_added_v0() {
+        let msg = ButtplugMessageUnion::DeviceAdded(DeviceAdded {
+            id: 0,
+            device_index: 0,
+            device_name: "Test Device".to_owned(),
+            device_messages: downgrade_test_device_messages(),
+        });
+        let js = serde_json::to_string(&msg.downgrade_to(MessageSpecVersion::Version0)).unwrap();
+        assert_eq!(
+            js,
+            r#"{"DeviceAdded":{"Id":0,"DeviceIndex":0,"DeviceName":"Test Device","DeviceMessages":["SingleMotorVibrateCmd"]}}"#
+        );
+    }
+
+    #[test]
+    fn test_downgrade_device_list_v0() {
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "VibrateCmd".to_owned(),
+            MessageAttributes {
+                feature_count: Some(2),
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "StopDeviceCmd".to_owned(),
+            MessageAttributes {
+                feature_count: None,
+                patterns: None,
+            },
+        );
+        let msg = ButtplugMessageUnion::DeviceList(DeviceList {
+            id: 3,
+            devices: vec![DeviceMessageInfo {
+                device_index: 1,
+                device_name: "Test Device".to_owned(),
+                device_messages,
+            }],
+        });
+        let downgraded = msg.downgrade_to(MessageSpecVersion::Version0);
+        assert_eq!(downgraded.name(), "DeviceList");
+        assert_eq!(downgraded.get_id(), 3);
+        assert_eq!(
+            serde_json::to_string(&downgraded).unwrap(),
+            r#"{"DeviceList":{"Id":3,"Devices":[{"DeviceIndex":1,"DeviceName":"Test Device","DeviceMessages":["SingleMotorVibrateCmd","StopDeviceCmd"]}]}}"#
+        );
+    }
+
+    #[test]
+    fn test_downgrade_vibrate_cmd() {
+        let mut msg = VibrateCmd::new(
+            2,
+            vec![
+                VibrateSubcommand::new(0, 0.25),
+                VibrateSubcommand::new(1, 0.5),
+            ],
+        );
+        msg.set_id(5);
+        let union = ButtplugMessageUnion::VibrateCmd(msg);
+        assert_eq!(union.downgrade_to(MessageSpecVersion::Version1), union);
+        let js = serde_json::to_string(&union.downgrade_to(MessageSpecVersion::Version0)).unwrap();
+        let mut expected = SingleMotorVibrateCmd::new(2, 0.5);
+        expected.set_id(5);
+        assert_eq!(
+            serde_json::from_str::<ButtplugMessageUnion>(&js).unwrap(),
+            ButtplugMessageUnion::SingleMotorVibrateCmd(expected)
+        );
+    }
+
+    #[test]
+    fn test_try_from_json() {
+        let msgs =
+            ButtplugMessageUnion::try_from_json(&format!("[{},{}]", OK_STR, ERROR_STR)).unwrap();
+        assert_eq!(
+            msgs,
+            vec![
+                ButtplugMessageUnion::Ok(Ok::new(0)),
+                ButtplugMessageUnion::Error(Error::new(ErrorCode::ErrorHandshake, "Test Error")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try_from_json_empty_array() {
+        let err = Error::from(ButtplugMessageUnion::try_from_json("[]").unwrap_err());
+        assert_eq!(err.error_code, ErrorCode::ErrorMessage);
+        assert_eq!(err.error_message, "Message array is empty.");
+    }
+
+    #[test]
+    fn test_try_from_json_unknown_message() {
+        let err =
+            ButtplugMessageUnion::try_from_json("[{\"NotAMessage\":{\"Id\":1}}]").unwrap_err();
+        match err {
+            ButtplugError::ButtplugMessageError(ButtplugMessageError::UnexpectedType(ref s)) => {
+                assert_eq!(s, "Unknown message type: NotAMessage")
+            }
+            _ => panic!("Unknown message should be an unexpected type"),
+        }
+    }
+
+    #[test]
+    fn test_try_from_json_bad_nested_enum() {
+        let err =
+            ButtplugMessageUnion::try_from_json(r#"[{"RequestLog":{"Id":1,"LogLevel":"Bogus"}}]"#)
+                .unwrap_err();
+        match err {
+            ButtplugError::ButtplugMessageError(ButtplugMessageError::DeserializationFailed(
+                ref s,
+            )) => assert!(s.starts_with("Invalid RequestLog message")),
+            _ => panic!("Bad log level should fail deserialization"),
+        }
+    }
+
+    #[test]
+    fn test_try_from_json_malformed() {
+        assert!(ButtplugMessageUnion::try_from_json("[{\"Ok\":").is_err());
+        assert!(ButtplugMessageUnion::try_from_json(OK_STR).is_err());
+        assert!(ButtplugMessageUnion::try_from_json("[{\"Ok\":{}}]").is_err());
+    }
+
+    #[test]
+    fn test_batch_round_trip() {
+        let added = DeviceAdded {
+            id: 0,
+            device_index: 1,
+            device_name: "Test Device".to_owned(),
+            device_messages: downgrade_test_device_messages(),
+        };
+        let mut vibrate = VibrateCmd::new(1, vec![VibrateSubcommand::new(0, 0.5)]);
+        vibrate.set_id(7);
+        let batch = vec![
+            ButtplugMessageUnion::Ok(Ok::new(3)),
+            ButtplugMessageUnion::DeviceAdded(added),
+            ButtplugMessageUnion::VibrateCmd(vibrate),
+        ];
+        let js = serialize_batch(&batch);
+        assert!(js.starts_with("[{\"Ok\":{\"Id\":3}},{\"DeviceAdded\":"));
+        let round_trip = deserialize_batch(&js).unwrap();
+        assert_eq!(round_trip, batch);
+        assert_eq!(
+            round_trip.iter().map(|m| m.get_id()).collect::<Vec<u32>>(),
+            vec![3, 0, 7]
+        );
+    }
+
+    #[test]
+    fn test_message_id_generator_unique() {
+        let generator = Arc::new(MessageIdGenerator::default());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let generator = generator.clone();
+                task::spawn(async move {
+                    (0..100)
+                        .map(|_| {
+                            let mut msg = Ping::default();
+                            generator.stamp(&mut msg);
+                            msg.get_id()
+                        })
+                        .collect::<Vec<u32>>()
+                })
+            })
+            .collect();
+        let ids: HashSet<u32> = task::block_on(async {
+            let mut ids = HashSet::new();
+            for handle in handles {
+                ids.extend(handle.await);
+            }
+            ids
+        });
+        assert_eq!(ids.len(), 800);
+        assert!(!ids.contains(&0));
+    }
+
+    #[test]
+    fn test_message_id_generator_skips_zero() {
+        let generator = MessageIdGenerator {
+            next_id: AtomicU32::new(u32::MAX),
+        };
+        assert_eq!(generator.next_id(), u32::MAX);
+        assert_eq!(generator.next_id(), 1);
+    }
+
+    #[test]
+    fn test_invalid_field_error_message() {
+        let msg = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 1.5)]);
+        match msg.is_valid().unwrap_err() {
+            ButtplugMessageError::InvalidField { ref field, .. } => assert_eq!(field, "Speeds"),
+            _ => panic!("Out of range speed should be an invalid field error"),
+        }
+        let err = Error::from(ButtplugError::from(msg.is_valid().unwrap_err()));
+        assert_eq!(err.error_code, ErrorCode::ErrorMessage);
+        assert!(err.error_message.contains("Speeds"));
+    }
+
+    #[test]
+    fn test_kiiroo_cmd_to_linear_cmd() {
+        let mut msg = KiirooCmd::new(2, "3");
+        msg.set_id(6);
+        let mut expected = LinearCmd::new(
+            2,
+            vec![VectorSubcommand::new(0, KIIROO_LINEAR_DURATION, 0.75)],
+        );
+        expected.set_id(6);
+        assert_eq!(LinearCmd::try_from(&msg).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_kiiroo_cmd_to_linear_cmd_invalid() {
+        for command in &["", "abc", "5", "-1", "2.5"] {
+            match LinearCmd::try_from(&KiirooCmd::new(0, command)).unwrap_err() {
+                ButtplugMessageError::InvalidField { ref field, .. } => {
+                    assert_eq!(field, "Command")
+                }
+                _ => panic!("Invalid Kiiroo command should be an invalid field error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_response_to() {
+        let mut vibrate = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]);
+        vibrate.set_id(4);
+        let vibrate = ButtplugMessageUnion::VibrateCmd(vibrate);
+        let ok = ButtplugMessageUnion::Ok(Ok::new(4));
+        assert!(ok.matches_id(4));
+        assert!(ok.is_response_to(&vibrate));
+        assert!(!ButtplugMessageUnion::Ok(Ok::new(5)).is_response_to(&vibrate));
+        let mut error = Error::new(ErrorCode::ErrorDevice, "Test Error");
+        error.set_id(4);
+        assert!(ButtplugMessageUnion::Error(error).is_response_to(&vibrate));
+        // Requests expecting a specific reply don't accept Ok.
+        let mut battery = BatteryLevelCmd::new(0);
+        battery.set_id(4);
+        let battery = ButtplugMessageUnion::BatteryLevelCmd(battery);
+        assert!(!ok.is_response_to(&battery));
+        let reading = BatteryLevelReading::new(4, 0, 0.5);
+        assert!(ButtplugMessageUnion::BatteryLevelReading(reading).is_response_to(&battery));
+        // Events are never replies.
+        let mut ping = Ping::default();
+        ping.set_id(0);
+        let event = ButtplugMessageUnion::Ok(Ok::new(0));
+        assert!(!event.is_response_to(&ButtplugMessageUnion::Ping(ping)));
+    }
+
+    #[test]
+    fn test_device_added_round_trip() {
+        let added = DeviceAdded {
+            id: 0,
+            device_index: 2,
+            device_name: "Test Device".to_owned(),
+            device_messages: downgrade_test_device_messages(),
+        };
+        let js = serde_json::to_string(&ButtplugMessageUnion::DeviceAdded(added.clone())).unwrap();
+        assert!(js.contains("\"VibrateCmd\":{\"FeatureCount\":2}"));
+        match serde_json::from_str(&js).unwrap() {
+            ButtplugMessageUnion::DeviceAdded(msg) => {
+                assert_eq!(msg, added);
+                assert_eq!(
+                    DeviceMessageInfo::from(&msg),
+                    DeviceMessageInfo {
+                        device_index: 2,
+                        device_name: "Test Device".to_owned(),
+                        device_messages: downgrade_test_device_messages(),
+                    }
+                );
+            }
+            _ => panic!("Should've deserialized a DeviceAdded"),
+        }
+    }
+
+    #[test]
+    fn test_message_attributes_omit_none() {
+        let attributes = MessageAttributes {
+            feature_count: Some(2),
+            patterns: None,
+        };
+        let js = serde_json::to_string(&attributes).unwrap();
+        assert_eq!(js, "{\"FeatureCount\":2}");
+        let attributes = MessageAttributes {
+            feature_count: None,
+            patterns: None,
+        };
+        let js = serde_json::to_string(&attributes).unwrap();
+        assert!(!js.contains("null"));
+        assert_eq!(
+            serde_json::from_str::<MessageAttributes>("{}").unwrap(),
+            attributes
+        );
+    }
+
+    #[test]
+    fn test_log_level_ordering() {
+        assert!(LogLevel::Off < LogLevel::Fatal);
+        assert!(LogLevel::Error < LogLevel::Trace);
+        assert!(LogLevel::Info <= LogLevel::Info);
+        let error: LogLevel =
+            serde_json::from_str(&serde_json::to_string(&LogLevel::Error).unwrap()).unwrap();
+        let trace: LogLevel =
+            serde_json::from_str(&serde_json::to_string(&LogLevel::Trace).unwrap()).unwrap();
+        assert!(error < trace);
+    }
+
+    const VIBRATE_CMD_EXTRA_FIELD_STR: &str =
+        "[{\"VibrateCmd\":{\"Id\":1,\"DeviceIndex\":0,\"Speeds\":[{\"Index\":0,\"Speed\":0.5}],\"Foo\":1}}]";
+
+    #[cfg(not(feature = "strict_parsing"))]
+    #[test]
+    fn test_unknown_field_ignored() {
+        let msgs = ButtplugMessageUnion::try_from_json(VIBRATE_CMD_EXTRA_FIELD_STR).unwrap();
+        assert_eq!(
+            msgs,
+            vec![ButtplugMessageUnion::VibrateCmd(VibrateCmd::new(
+                0,
+                vec![VibrateSubcommand::new(0, 0.5)]
+            ))]
+        );
+    }
+
+    #[cfg(feature = "strict_parsing")]
+    #[test]
+    fn test_unknown_field_rejected() {
+        let err = ButtplugMessageUnion::try_from_json(VIBRATE_CMD_EXTRA_FIELD_STR).unwrap_err();
+        match err {
+            ButtplugError::ButtplugMessageError(ButtplugMessageError::DeserializationFailed(
+                ref s,
+            )) => assert!(s.contains("Foo")),
+            _ => panic!("Unknown field should fail deserialization"),
+        }
+    }
+
+    #[test]
+    fn test_fleshlight_launch_fw12_cmd_valid() {
+        assert!(FleshlightLaunchFW12Cmd::new(0, 99, 99).is_valid().is_ok());
+        assert!(FleshlightLaunchFW12Cmd::new(0, 0, 0).is_valid().is_ok());
+        match FleshlightLaunchFW12Cmd::new(0, 100, 50)
+            .is_valid()
+            .unwrap_err()
+        {
+            ButtplugMessageError::InvalidField { ref field, .. } => assert_eq!(field, "Position"),
+            _ => panic!("Position of 100 should be an invalid field error"),
+        }
+        match FleshlightLaunchFW12Cmd::new(0, 50, 100)
+            .is_valid()
+            .unwrap_err()
+        {
+            ButtplugMessageError::InvalidField { ref field, .. } => assert_eq!(field, "Speed"),
+            _ => panic!("Speed of 100 should be an invalid field error"),
+        }
+    }
+
+    #[test]
+    fn test_vibrate_cmd_new_uniform() {
+        let msg = VibrateCmd::new_uniform(1, 3, 0.25);
+        assert_eq!(msg.device_index, 1);
+        assert_eq!(msg.speeds.len(), 3);
+        for (i, cmd) in msg.speeds.iter().enumerate() {
+            assert_eq!(cmd.index, i as u32);
+            assert_eq!(cmd.speed, 0.25);
+        }
+        assert!(msg.is_valid().is_ok());
+    }
+
+    fn diff_test_device(device_index: u32, device_name: &str) -> DeviceMessageInfo {
+        DeviceMessageInfo {
+            device_index,
+            device_name: device_name.to_owned(),
+            device_messages: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_device_feature_counts() {
+        let mut device = diff_test_device(0, "Vibrator");
+        device.device_messages.insert(
+            "VibrateCmd".to_owned(),
+            MessageAttributes {
+                feature_count: Some(2),
+                patterns: None,
+            },
+        );
+        assert_eq!(device.vibrate_feature_count(), Some(2));
+        assert_eq!(device.rotate_feature_count(), None);
+        assert_eq!(device.linear_feature_count(), None);
+        let device = diff_test_device(1, "No Features");
+        assert_eq!(device.vibrate_feature_count(), None);
+        assert_eq!(device.rotate_feature_count(), None);
+        assert_eq!(device.linear_feature_count(), None);
+    }
+
+    #[test]
+    fn test_device_list_diff() {
+        let previous = DeviceList {
+            id: 1,
+            devices: vec![
+                diff_test_device(0, "Constant"),
+                diff_test_device(1, "Removed"),
+            ],
+        };
+        let current = DeviceList {
+            id: 1,
+            devices: vec![
+                diff_test_device(0, "Constant"),
+                diff_test_device(2, "Added"),
+            ],
+        };
+        let diff = current.diff(&previous);
+        assert_eq!(diff.added, vec![diff_test_device(2, "Added")]);
+        assert_eq!(diff.removed, vec![diff_test_device(1, "Removed")]);
+        assert_eq!(diff.unchanged, vec![diff_test_device(0, "Constant")]);
+    }
+
+    #[test]
+    fn test_device_list_diff_changed_device() {
+        let previous = DeviceList {
+            id: 1,
+            devices: vec![diff_test_device(0, "Old")],
+        };
+        let current = DeviceList {
+            id: 1,
+            devices: vec![diff_test_device(0, "New")],
+        };
+        let diff = current.diff(&previous);
+        assert_eq!(diff.added, vec![diff_test_device(0, "New")]);
+        assert_eq!(diff.removed, vec![diff_test_device(0, "Old")]);
+        assert!(diff.unchanged.is_empty());
+        assert_eq!(current.diff(&current).unchanged, current.devices);
+    }
+
+    #[test]
+    fn test_union_names() {
+        assert_eq!(Ping::default().as_union().name(), "Ping");
+        assert_eq!(VibrateCmd::default().as_union().name(), "VibrateCmd");
+        assert_eq!(
+            FleshlightLaunchFW12Cmd::default().as_union().name(),
+            "FleshlightLaunchFW12Cmd"
+        );
+        assert_eq!(OscillateCmd::default().as_union().name(), "OscillateCmd");
+        assert_eq!(
+            ButtplugMessageUnion::ALL_NAMES.len(),
+            ButtplugMessageUnion::VARIANT_COUNT
+        );
+        let unique: HashSet<_> = ButtplugMessageUnion::ALL_NAMES.iter().collect();
+        assert_eq!(unique.len(), ButtplugMessageUnion::ALL_NAMES.len());
+        assert!(ButtplugMessageUnion::ALL_NAMES.contains(&"RSSILevelReading"));
+    }
+
+    #[test]
+    fn test_oscillate_cmd_serialize() {
+        let msg = ButtplugMessageUnion::OscillateCmd(OscillateCmd::new(
+            2,
+            vec![OscillateSubcommand::new(0, 0.5)],
+        ));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(js, OSCILLATE_CMD_STR);
+    }
+
+    #[test]
+    fn test_oscillate_cmd_deserialize() {
+        let union: ButtplugClientMessageUnion = serde_json::from_str(OSCILLATE_CMD_STR).unwrap();
+        assert_eq!(
+            ButtplugClientMessageUnion::OscillateCmd(OscillateCmd::new(
+                2,
+                vec![OscillateSubcommand::new(0, 0.5)]
+            )),
+            union
+        );
+    }
+
+    #[test]
+    fn test_oscillate_cmd_valid() {
+        assert!(OscillateCmd::new(0, vec![OscillateSubcommand::new(0, 1.0)])
+            .is_valid()
+            .is_ok());
+        assert!(OscillateCmd::new(0, vec![]).is_valid().is_err());
+        assert!(OscillateCmd::new(0, vec![OscillateSubcommand::new(0, 1.5)])
+            .is_valid()
+            .is_err());
+        assert!(OscillateCmd::new(
+            0,
+            vec![
+                OscillateSubcommand::new(0, 0.5),
+                OscillateSubcommand::new(0, 0.5)
+            ]
+        )
+        .is_valid()
+        .is_err());
+    }
+
+    #[test]
+    fn test_rotate_cmd_valid() {
+        assert!(
+            RotateCmd::new(0, vec![RotationSubcommand::new(0, 1.0, true)])
+                .is_valid()
+                .is_ok()
+        );
+        assert!(RotateCmd::new(0, vec![]).is_valid().is_err());
+        assert!(
+            RotateCmd::new(0, vec![RotationSubcommand::new(0, 1.5, true)])
+                .is_valid()
+                .is_err()
+        );
+        match RotateCmd::new(
+            0,
+            vec![
+                RotationSubcommand::new(0, 0.5, true),
+                RotationSubcommand::new(0, 0.5, false),
+            ],
+        )
+        .is_valid()
+        .unwrap_err()
+        {
+            ButtplugMessageError::InvalidField { field, reason } => {
+                assert_eq!(field, "Rotations");
+                assert_eq!(reason, "RotateCmd contains multiple speeds for index 0.");
+            }
+            _ => panic!("Should've received invalid field error"),
+        }
+    }
+
+    #[test]
+    fn test_pattern_cmd_serialize() {
+        let msg = ButtplugMessageUnion::PatternCmd(PatternCmd::new(2, "Wave", None));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(js, PATTERN_CMD_STR);
+        let msg = PatternCmd::new(2, "Wave", Some(1));
+        let js = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            js,
+            "{\"Id\":1,\"DeviceIndex\":2,\"PatternName\":\"Wave\",\"Index\":1}"
+        );
+    }
+
+    #[test]
+    fn test_pattern_cmd_deserialize() {
+        let union: ButtplugMessageUnion = serde_json::from_str(PATTERN_CMD_STR).unwrap();
+        assert_eq!(
+            ButtplugMessageUnion::PatternCmd(PatternCmd::new(2, "Wave", None)),
+            union
+        );
+        let client_union: ButtplugClientMessageUnion =
+            serde_json::from_str(PATTERN_CMD_STR).unwrap();
+        assert_eq!(
+            ButtplugClientMessageUnion::PatternCmd(PatternCmd::new(2, "Wave", None)),
+            client_union
+        );
+    }
+
+    #[test]
+    fn test_pattern_cmd_valid_for() {
+        let msg = PatternCmd::new(0, "Wave", None);
+        let mut attributes = MessageAttributes {
+            feature_count: None,
+            patterns: None,
+        };
+        assert!(msg.is_valid_for(&attributes).is_err());
+        attributes.patterns = Some(vec!["Wave".to_owned()]);
+        assert!(msg.is_valid_for(&attributes).is_ok());
+        let msg = PatternCmd::new(0, "Pulse", None);
+        match msg.is_valid_for(&attributes).unwrap_err() {
+            ButtplugMessageError::InvalidField { field, .. } => assert_eq!(field, "PatternName"),
+            _ => panic!("Should've received invalid field error"),
+        }
+    }
+
+    #[test]
+    fn test_default_ids_by_direction() {
+        let client_msgs: Vec<ButtplugClientMessageUnion> = vec![
+            ButtplugClientMessageUnion::Ping(Ping::default()),
+            ButtplugClientMessageUnion::Test(Test::default()),
+            ButtplugClientMessageUnion::RequestServerInfo(RequestServerInfo::default()),
+            ButtplugClientMessageUnion::StartScanning(StartScanning::default()),
+            ButtplugClientMessageUnion::StopScanning(StopScanning::default()),
+            ButtplugClientMessageUnion::RequestDeviceList(RequestDeviceList::default()),
+            ButtplugClientMessageUnion::VibrateCmd(VibrateCmd::default()),
+            ButtplugClientMessageUnion::LinearCmd(LinearCmd::default()),
+            ButtplugClientMessageUnion::RotateCmd(RotateCmd::default()),
+            ButtplugClientMessageUnion::FleshlightLaunchFW12Cmd(FleshlightLaunchFW12Cmd::default()),
+            ButtplugClientMessageUnion::VorzeA10CycloneCmd(VorzeA10CycloneCmd::default()),
+            ButtplugClientMessageUnion::SingleMotorVibrateCmd(SingleMotorVibrateCmd::default()),
+            ButtplugClientMessageUnion::StopDeviceCmd(StopDeviceCmd::default()),
+            ButtplugClientMessageUnion::StopAllDevices(StopAllDevices::default()),
+            ButtplugClientMessageUnion::BatteryLevelCmd(BatteryLevelCmd::default()),
+            ButtplugClientMessageUnion::RSSILevelCmd(RSSILevelCmd::default()),
+            ButtplugClientMessageUnion::PatternCmd(PatternCmd::default()),
+            ButtplugClientMessageUnion::OscillateCmd(OscillateCmd::default()),
+        ];
+        for msg in client_msgs {
+            let msg = ButtplugMessageUnion::from(msg);
+            assert_eq!(msg.get_id(), 1, "{} should default to id 1", msg);
+        }
+        let server_msgs = vec![
+            ButtplugMessageUnion::Ok(Ok::default()),
+            ButtplugMessageUnion::ServerInfo(ServerInfo::default()),
+            ButtplugMessageUnion::DeviceList(DeviceList::default()),
+            ButtplugMessageUnion::DeviceAdded(DeviceAdded::default()),
+            ButtplugMessageUnion::DeviceRemoved(DeviceRemoved::default()),
+            ButtplugMessageUnion::ScanningFinished(ScanningFinished::default()),
+            ButtplugMessageUnion::BatteryLevelReading(BatteryLevelReading::default()),
+            ButtplugMessageUnion::RSSILevelReading(RSSILevelReading::default()),
+        ];
+        for msg in server_msgs {
+            assert_eq!(msg.get_id(), 0, "{} should default to id 0", msg);
+        }
+    }
+
+    #[test]
+    fn test_clone_with_id() {
+        let msg = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]);
+        let clone = msg.clone_with_id(5);
+        assert_eq!(msg.get_id(), 1);
+        assert_eq!(clone.get_id(), 5);
+        assert_eq!(clone.speeds, msg.speeds);
+        let union = ButtplugMessageUnion::Ok(Ok::new(1));
+        assert_eq!(union.clone_with_id(3), ButtplugMessageUnion::Ok(Ok::new(3)));
+        assert_eq!(union.get_id(), 1);
+    }
+
+    #[test]
+    fn test_protocol_json_array() {
+        let old_format = "[".to_owned() + OK_STR + "]";
+        assert_eq!(Ok::new(0).as_protocol_json(), old_format);
+        assert_eq!(
+            ButtplugMessageUnion::Ok(Ok::new(0)).as_protocol_json(),
+            old_format
+        );
+        let array: ButtplugMessageArray = serde_json::from_str(&old_format).unwrap();
+        assert_eq!(
+            array.into_iter().collect::<Vec<ButtplugMessageUnion>>(),
+            vec![ButtplugMessageUnion::Ok(Ok::new(0))]
+        );
+        let array: ButtplugMessageArray = vec![
+            ButtplugMessageUnion::Ok(Ok::new(1)),
+            ButtplugMessageUnion::Ok(Ok::new(2)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            serde_json::to_string(&array).unwrap(),
+            "[{\"Ok\":{\"Id\":1}},{\"Ok\":{\"Id\":2}}]"
+        );
+    }
+
+    #[test]
+    fn test_linear_cmd_valid() {
+        let msg = LinearCmd::new(
+            0,
+            vec![
+                VectorSubcommand::new(0, 500, 0.0),
+                VectorSubcommand::new(1, 500, 1.0),
+            ],
+        );
+        assert!(msg.is_valid().is_ok());
+    }
+
+    #[test]
+    fn test_linear_cmd_position_out_of_range() {
+        let msg = LinearCmd::new(0, vec![VectorSubcommand::new(0, 500, 1.1)]);
+        assert!(msg.is_valid().is_err());
+        let msg = LinearCmd::new(0, vec![VectorSubcommand::new(0, 500, -0.1)]);
+        assert!(msg.is_valid().is_err());
+    }
+
+    #[test]
+    fn test_linear_cmd_duplicate_index() {
+        let msg = LinearCmd::new(
+            0,
+            vec![
+                VectorSubcommand::new(0, 500, 0.5),
+                VectorSubcommand::new(0, 1000, 0.2),
+            ],
+        );
+        match msg.is_valid().unwrap_err() {
+            ButtplugMessageError::InvalidField { field, .. } => assert_eq!(field, "Vectors"),
+            _ => panic!("Should've received invalid field error"),
+        }
+    }
+
+    #[test]
+    fn test_vibrate_cmd_valid() {
+        let msg = VibrateCmd::new(
+            0,
+            vec![
+                VibrateSubcommand::new(0, 0.0),
+                VibrateSubcommand::new(1, 1.0),
+            ],
+        );
+        assert!(msg.is_valid().is_ok());
+    }
+
+    #[test]
+    fn test_vibrate_cmd_empty_speeds() {
+        let msg = VibrateCmd::new(0, vec![]);
+        match msg.is_valid().unwrap_err() {
+            ButtplugMessageError::InvalidField { field, reason } => {
+                assert_eq!(field, "Speeds");
+                assert_eq!(reason, "VibrateCmd requires at least one speed.");
+            }
+            _ => panic!("Should've received invalid field error"),
+        }
+    }
+
+    #[test]
+    fn test_vibrate_cmd_negative_speed() {
+        let msg = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, -0.1)]);
+        assert!(msg.is_valid().is_err());
+    }
+
+    #[test]
+    fn test_vibrate_cmd_speed_above_max() {
+        let msg = VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 1.1)]);
+        assert!(msg.is_valid().is_err());
+    }
+
+    #[test]
+    fn test_vibrate_cmd_duplicate_index() {
+        let msg = VibrateCmd::new(
+            0,
+            vec![
+                VibrateSubcommand::new(0, 0.5),
+                VibrateSubcommand::new(0, 0.5),
+            ],
+        );
+        assert!(msg.is_valid().is_err());
+    }
+
+    #[test]
+    fn test_single_motor_vibrate_cmd_upgrade() {
+        let mut msg = SingleMotorVibrateCmd::new(2, 0.5);
+        msg.set_id(7);
+        let vibrate_cmd = VibrateCmd::from((&msg, 2));
+        assert_eq!(vibrate_cmd.get_id(), 7);
+        assert_eq!(vibrate_cmd.device_index, 2);
+        assert_eq!(
+            vibrate_cmd.speeds,
+            vec![
+                VibrateSubcommand::new(0, 0.5),
+                VibrateSubcommand::new(1, 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_message_union_conversion() {
+        let msg = ButtplugMessageUnion::VibrateCmd(VibrateCmd::new(0, vec![]));
+        let client_msg = ButtplugClientMessageUnion::try_from(msg.clone()).unwrap();
+        assert_eq!(
+            client_msg,
+            ButtplugClientMessageUnion::VibrateCmd(VibrateCmd::new(0, vec![]))
+        );
+        assert_eq!(ButtplugMessageUnion::from(client_msg), msg);
+        assert!(
+            ButtplugClientMessageUnion::try_from(ButtplugMessageUnion::DeviceAdded(
+                DeviceAdded::default()
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_server_message_union_conversion() {
+        let msg = ButtplugMessageUnion::DeviceAdded(DeviceAdded::default());
+        let server_msg = ButtplugServerMessageUnion::try_from(msg.clone()).unwrap();
+        assert_eq!(
+            server_msg,
+            ButtplugServerMessageUnion::DeviceAdded(DeviceAdded::default())
+        );
+        assert_eq!(ButtplugMessageUnion::from(server_msg), msg);
+        assert_eq!(
+            ButtplugServerMessageUnion::try_from(ButtplugMessageUnion::VibrateCmd(
+                VibrateCmd::new(0, vec![])
+            ))
+            .unwrap_err(),
+            ButtplugMessageError::UnexpectedType(
+                "VibrateCmd cannot be sent from server to client.".to_owned()
+            )
+        );
+        let downgraded = msg.downgrade_to(MessageSpecVersion::Version0);
+        assert!(downgraded.is_server_message());
+        assert!(!downgraded.is_client_message());
+        let server_msg = ButtplugServerMessageUnion::try_from(downgraded.clone()).unwrap();
+        assert_eq!(ButtplugMessageUnion::from(server_msg), downgraded);
+    }
+
+    #[test]
+    fn test_bidirectional_message_union_conversion() {
+        let msg = ButtplugMessageUnion::Test(Test::new("Test"));
+        assert!(msg.is_client_message());
+        assert!(msg.is_server_message());
+        assert!(ButtplugClientMessageUnion::try_from(msg.clone()).is_ok());
+        assert!(ButtplugServerMessageUnion::try_from(msg).is_ok());
+    }
+
+    #[test]
+    fn test_system_message_classification() {
+        let system_msgs = vec![
+            ButtplugMessageUnion::Ok(Ok::new(1)),
+            ButtplugMessageUnion::Ping(Ping::default()),
+            ButtplugMessageUnion::RequestServerInfo(RequestServerInfo::new(
+                "Test Client",
+                MessageSpecVersion::Version1,
+            )),
+            ButtplugMessageUnion::DeviceAdded(DeviceAdded::default()),
+            ButtplugMessageUnion::StopAllDevices(StopAllDevices::default()),
+        ];
+        for msg in system_msgs {
+            assert!(msg.is_system_message());
+            assert_eq!(msg.device_index(), None);
+        }
+    }
+
+    #[test]
+    fn test_device_message_classification() {
+        let device_msgs = vec![
+            ButtplugMessageUnion::VibrateCmd(VibrateCmd::new(3, vec![])),
+            ButtplugMessageUnion::StopDeviceCmd(StopDeviceCmd::new(3)),
+            ButtplugMessageUnion::BatteryLevelReading(BatteryLevelReading::new(1, 3, 1.0)),
+        ];
+        for msg in device_msgs {
+            assert!(!msg.is_system_message());
+            assert_eq!(msg.device_index(), Some(3));
+        }
+    }
+
+    #[test]
+    fn test_union_display() {
+        let mut vibrate = VibrateCmd::new(2, vec![VibrateSubcommand::new(0, 0.5)]);
+        vibrate.set_id(7);
+        assert_eq!(
+            format!("{}", ButtplugMessageUnion::VibrateCmd(vibrate)),
+            "VibrateCmd(id=7, device=2)"
+        );
+        assert_eq!(
+            format!("{}", ButtplugMessageUnion::Ok(Ok::new(3))),
+            "Ok(id=3)"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                ButtplugMessageUnion::StopAllDevices(StopAllDevices::default())
+            ),
+            "StopAllDevices(id=1)"
+        );
+    }
+
+    #[test]
+    fn test_message_name_const() {
+        assert_eq!(Ok::MESSAGE_NAME, "Ok");
+        assert_eq!(VibrateCmd::MESSAGE_NAME, "VibrateCmd");
+        assert_eq!(BatteryLevelReading::MESSAGE_NAME, "BatteryLevelReading");
+    }
+}
+
+#[cfg(feature = "serialize_cbor")]
+#[cfg(test)]
+mod cbor_test {
+    use super::{ButtplugMessageUnion, Error, ErrorCode, Ok, VibrateCmd, VibrateSubcommand};
+
+    fn round_trip(msg: ButtplugMessageUnion) {
+        let data = msg.to_cbor();
+        assert_eq!(ButtplugMessageUnion::try_from_cbor(&data).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_ok_cbor_round_trip() {
+        round_trip(ButtplugMessageUnion::Ok(Ok::new(5)));
+    }
+
+    #[test]
+    fn test_error_cbor_round_trip() {
+        round_trip(ButtplugMessageUnion::Error(Error::new(
+            ErrorCode::ErrorDevice,
+            "Test Error",
+        )));
+    }
+
+    #[test]
+    fn test_vibrate_cmd_cbor_round_trip() {
+        round_trip(ButtplugMessageUnion::VibrateCmd(VibrateCmd::new(
+            1,
+            vec![
+                VibrateSubcommand::new(0, 0.5),
+                VibrateSubcommand::new(1, 1.0),
+            ],
+        )));
+    }
+
+    #[test]
+    fn test_malformed_cbor() {
+        assert!(ButtplugMessageUnion::try_from_cbor(&[0xff, 0x00]).is_err());
+    }
+}
+
+#[cfg(feature = "serialize_msgpack")]
+#[cfg(test)]
+mod msgpack_test {
+    use super::{
+        ButtplugMessageUnion, DeviceList, DeviceMessageInfo, Error, ErrorCode, MessageAttributes,
+        MessageSpecVersion, ServerInfo,
+    };
+    use std::collections::HashMap;
+
+    fn round_trip(msg: ButtplugMessageUnion) {
+        let data = msg.to_msgpack();
+        assert_eq!(ButtplugMessageUnion::try_from_msgpack(&data).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_error_msgpack_round_trip() {
+        round_trip(ButtplugMessageUnion::Error(Error::new(
+            ErrorCode::ErrorDevice,
+            "Test Error",
+        )));
+    }
+
+    #[test]
+    fn test_error_code_msgpack_repr() {
+        // ErrorCode is encoded as its integer value, the same as in JSON.
+        let data = rmp_serde::to_vec_named(&ErrorCode::ErrorDevice).unwrap();
+        assert_eq!(data, vec![0x04]);
+    }
+
+    #[test]
+    fn test_server_info_msgpack_round_trip() {
+        round_trip(ButtplugMessageUnion::ServerInfo(ServerInfo::new(
+            "Test Server",
+            MessageSpecVersion::Version1,
+            500,
+        )));
+    }
+
+    #[test]
+    fn test_device_list_msgpack_round_trip() {
+        // Exercises optional fields that are skipped when empty.
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "VibrateCmd".to_owned(),
+            MessageAttributes {
+                feature_count: Some(2),
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "StopDeviceCmd".to_owned(),
+            MessageAttributes {
+                feature_count: None,
+                patterns: None,
+            },
+        );
+        round_trip(ButtplugMessageUnion::DeviceList(DeviceList {
+            id: 1,
+            devices: vec![DeviceMessageInfo {
+                device_index: 0,
+                device_name: "Test Device".to_owned(),
+                device_messages,
+            }],
+        }));
+    }
+
+    #[test]
+    fn test_malformed_msgpack() {
+        assert!(ButtplugMessageUnion::try_from_msgpack(&[0xc1]).is_err());
+    }
+}
+
+#[cfg(feature = "serialize_json")]
+#[cfg(test)]
+mod schema_test {
+    use super::{
+        BatteryLevelCmd, ButtplugMessage, ButtplugMessageUnion, DeviceAdded, Error, ErrorCode,
+        MessageAttributes, Ok, OscillateCmd, OscillateSubcommand, PatternCmd, RSSILevelCmd,
+        VibrateCmd, VibrateSubcommand,
+    };
+    use jsonschema::JSONSchema;
+    use std::collections::HashMap;
+
+    const SCHEMA: &str = include_str!("buttplug-schema.json");
+
+    fn validate_json(json: &str) -> Result<(), Vec<String>> {
+        let schema: serde_json::Value = serde_json::from_str(SCHEMA).unwrap();
+        let compiled = JSONSchema::compile(&schema, None).unwrap();
+        let instance: serde_json::Value = serde_json::from_str(json).unwrap();
+        let result = compiled
+            .validate(&instance)
+            .map_err(|errors| errors.map(|error| error.to_string()).collect());
+        result
+    }
+
+    fn assert_conforms(msg: ButtplugMessageUnion) {
+        let json = msg.as_protocol_json();
+        if let Err(errors) = validate_json(&json) {
+            panic!("{} does not match schema: {:?}", json, errors);
+        }
+    }
+
+    #[test]
+    fn test_ok_schema() {
+        assert_conforms(Ok::new(1).as_union());
+    }
+
+    #[test]
+    fn test_error_schema() {
+        assert_conforms(Error::new(ErrorCode::ErrorDevice, "Test Error").as_union());
+    }
+
+    #[test]
+    fn test_vibrate_cmd_schema() {
+        assert_conforms(
+            VibrateCmd::new(
+                0,
+                vec![
+                    VibrateSubcommand::new(0, 0.5),
+                    VibrateSubcommand::new(1, 1.0),
+                ],
+            )
+            .as_union(),
+        );
+    }
+
+    #[test]
+    fn test_device_added_schema() {
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "VibrateCmd".to_owned(),
+            MessageAttributes {
+                feature_count: Some(2),
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "StopDeviceCmd".to_owned(),
+            MessageAttributes {
+                feature_count: None,
+                patterns: None,
+            },
+        );
+        assert_conforms(DeviceAdded::new(0, "Test Device", device_messages).as_union());
+    }
+
+    #[test]
+    fn test_schema_rejects_nonconforming_messages() {
+        // Make sure the harness would actually catch a renamed or missing
+        // field.
+        assert!(validate_json("[{\"Ok\":{\"id\":1}}]").is_err());
+        assert!(validate_json("[{\"VibrateCmd\":{\"Id\":1,\"DeviceIndex\":0}}]").is_err());
+    }
+
+    #[test]
+    fn test_extensions_are_known_nonconformant() {
+        // Messages and attributes this library adds to the spec aren't in the
+        // published schema. If one of these starts validating, it has made it
+        // into the schema, and belongs in the conformance tests above.
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "PatternCmd".to_owned(),
+            MessageAttributes {
+                feature_count: None,
+                patterns: Some(vec!["Wave".to_owned()]),
+            },
+        );
+        let extensions = vec![
+            OscillateCmd::new(0, vec![OscillateSubcommand::new(0, 0.5)]).as_union(),
+            PatternCmd::new(0, "Wave", None).as_union(),
+            BatteryLevelCmd::new(0).as_union(),
+            RSSILevelCmd::new(0).as_union(),
+            DeviceAdded::new(0, "Test Device", device_messages).as_union(),
+        ];
+        for msg in extensions {
+            assert!(
+                validate_json(&msg.clone().as_protocol_json()).is_err(),
+                "{} now conforms to the schema",
+                msg.name()
+            );
+        }
+    }
 }
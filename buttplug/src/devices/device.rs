@@ -0,0 +1,244 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Connected devices, split into the communication bus specific
+//! [DeviceImpl] and the device specific [ButtplugProtocol].
+
+use crate::core::{
+    errors::ButtplugError,
+    messages::{
+        self, ButtplugMessage, ButtplugMessageType, ButtplugMessageUnion, MessageAttributes,
+        RotateCmd, RotationSubcommand, StopDeviceCmd, VibrateCmd, VibrateSubcommand,
+    },
+};
+use async_std::sync::{Mutex, Receiver};
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Arc};
+
+/// Device communication endpoints. Which ones a device has depends on its
+/// protocol, Lovense devices take commands on Tx and reply on Rx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Tx,
+    Rx,
+}
+
+/// Raw communication with a device, over bluetooth, serial, etc...
+#[async_trait]
+pub trait DeviceImpl: Send + Sync {
+    fn name(&self) -> &str;
+    /// Platform specific address (BLE address, serial port, etc...).
+    fn address(&self) -> &str;
+    async fn write_value(&self, endpoint: Endpoint, data: &[u8]) -> Result<(), ButtplugError>;
+    /// Returns a receiver for data the device sends back on
+    /// [Endpoint::Rx].
+    fn receiver(&self) -> Receiver<Vec<u8>>;
+}
+
+/// Writes to a device, retrying once if the write fails. Protocols should
+/// use this instead of [DeviceImpl::write_value], so a transient failure
+/// (a dropped BLE packet, for instance) doesn't lose the command.
+pub async fn write_with_retry(
+    device: &dyn DeviceImpl,
+    endpoint: Endpoint,
+    data: &[u8],
+) -> Result<(), ButtplugError> {
+    if let Err(err) = device.write_value(endpoint, data).await {
+        warn!("Write to {} failed, retrying: {}", device.name(), err);
+        return device.write_value(endpoint, data).await;
+    }
+    Ok(())
+}
+
+/// Turns Buttplug device messages into commands for a specific kind of
+/// device.
+#[async_trait]
+pub trait ButtplugProtocol: Send {
+    /// Runs any setup the device needs once connected, like querying its
+    /// model. Called once, before any messages are parsed.
+    async fn initialize(&mut self, device: &dyn DeviceImpl) -> Result<(), ButtplugError>;
+    /// Messages to advertise in DeviceAdded. Only called after
+    /// initialization.
+    fn device_messages(&self) -> HashMap<String, MessageAttributes>;
+    /// Sends the commands for a message to the device, and returns the
+    /// reply for the client.
+    async fn parse_message(
+        &mut self,
+        device: &dyn DeviceImpl,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError>;
+    /// Halts every actuator on the device. Used for StopDeviceCmd, which is
+    /// never handed to [ButtplugProtocol::parse_message].
+    ///
+    /// By default this sends the messages from [stop_messages] through
+    /// [ButtplugProtocol::parse_message]. Protocols with their own stop
+    /// command can override it.
+    async fn stop_device(
+        &mut self,
+        device: &dyn DeviceImpl,
+        msg: &StopDeviceCmd,
+    ) -> Result<(), ButtplugError> {
+        for stop_msg in stop_messages(msg.device_index, &self.device_messages()) {
+            self.parse_message(device, &stop_msg).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds the messages that stop every actuator advertised in
+/// `device_messages`: a zero speed VibrateCmd for all vibrators and a zero
+/// speed RotateCmd for all rotators. Linear actuators hold their position
+/// once a move finishes, so they don't need anything sent.
+pub fn stop_messages(
+    device_index: u32,
+    device_messages: &HashMap<String, MessageAttributes>,
+) -> Vec<ButtplugMessageUnion> {
+    let feature_count = |message_name: &str| {
+        device_messages
+            .get(message_name)
+            .and_then(|attributes| attributes.feature_count)
+            .unwrap_or(0)
+    };
+    let mut stop_messages = vec![];
+    let vibrator_count = feature_count(VibrateCmd::MESSAGE_NAME);
+    if vibrator_count > 0 {
+        let speeds = (0..vibrator_count)
+            .map(|index| VibrateSubcommand::new(index, 0.0))
+            .collect();
+        stop_messages.push(VibrateCmd::new(device_index, speeds).as_union());
+    }
+    let rotator_count = feature_count(RotateCmd::MESSAGE_NAME);
+    if rotator_count > 0 {
+        let rotations = (0..rotator_count)
+            .map(|index| RotationSubcommand::new(index, 0.0, true))
+            .collect();
+        stop_messages.push(RotateCmd::new(device_index, rotations).as_union());
+    }
+    stop_messages
+}
+
+/// A connected device along with the protocol used to talk to it.
+pub struct ButtplugDevice {
+    device: Arc<dyn DeviceImpl>,
+    protocol: Mutex<Box<dyn ButtplugProtocol>>,
+    device_messages: HashMap<String, MessageAttributes>,
+}
+
+impl ButtplugDevice {
+    /// Initializes the protocol for the device.
+    pub async fn new(
+        device: Arc<dyn DeviceImpl>,
+        mut protocol: Box<dyn ButtplugProtocol>,
+    ) -> Result<Self, ButtplugError> {
+        protocol.initialize(&*device).await?;
+        let device_messages = protocol.device_messages();
+        Ok(Self {
+            device,
+            protocol: Mutex::new(protocol),
+            device_messages,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn address(&self) -> &str {
+        self.device.address()
+    }
+
+    pub fn device_messages(&self) -> &HashMap<String, MessageAttributes> {
+        &self.device_messages
+    }
+
+    /// Hands a device message to the protocol. Messages are handled one at
+    /// a time, in the order they arrive.
+    pub async fn parse_message(
+        &self,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError> {
+        let mut protocol = self.protocol.lock().await;
+        match msg {
+            ButtplugMessageUnion::StopDeviceCmd(ref m) => {
+                protocol.stop_device(&*self.device, m).await?;
+                Ok(messages::Ok::new(msg.get_id()).as_union())
+            }
+            _ => protocol.parse_message(&*self.device, msg).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::devices::test_device::{TestDeviceImpl, TestProtocol};
+    use async_std::task;
+
+    #[test]
+    fn test_write_with_retry() {
+        let device = TestDeviceImpl::new("Test Device", "test");
+        task::block_on(async {
+            device.fail_writes(1);
+            write_with_retry(&device, Endpoint::Tx, b"first")
+                .await
+                .unwrap();
+            device.fail_writes(2);
+            let err = write_with_retry(&device, Endpoint::Tx, b"second")
+                .await
+                .unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorDevice
+            );
+        });
+        assert_eq!(
+            device.take_written(),
+            vec![(Endpoint::Tx, b"first".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_default_stop_device() {
+        let mut device_messages = HashMap::new();
+        for (message_name, feature_count) in &[
+            (VibrateCmd::MESSAGE_NAME, Some(2)),
+            (RotateCmd::MESSAGE_NAME, Some(1)),
+            (messages::LinearCmd::MESSAGE_NAME, Some(1)),
+            (StopDeviceCmd::MESSAGE_NAME, None),
+        ] {
+            device_messages.insert(
+                message_name.to_string(),
+                MessageAttributes {
+                    feature_count: *feature_count,
+                    patterns: None,
+                },
+            );
+        }
+        let mut protocol = TestProtocol::new(device_messages);
+        let device = TestDeviceImpl::new("Test Device", "test");
+        task::block_on(async {
+            protocol
+                .stop_device(&device, &StopDeviceCmd::new(3))
+                .await
+                .unwrap();
+        });
+        assert_eq!(
+            *protocol.received().lock().unwrap(),
+            vec![
+                VibrateCmd::new(
+                    3,
+                    vec![
+                        VibrateSubcommand::new(0, 0.0),
+                        VibrateSubcommand::new(1, 0.0)
+                    ]
+                )
+                .as_union(),
+                RotateCmd::new(3, vec![RotationSubcommand::new(0, 0.0, true)]).as_union()
+            ]
+        );
+    }
+}
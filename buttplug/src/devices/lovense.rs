@@ -0,0 +1,564 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Lovense protocol. Commands are ASCII strings ending in `;`, written to
+//! [Endpoint::Tx].
+
+use super::{
+    device::{write_with_retry, ButtplugProtocol, DeviceImpl, Endpoint},
+    lovense_model, lovense_model_for_type, lovense_vibrate_bytes, speed_to_step, LovenseModel,
+    RotationState, VibrateSpeeds,
+};
+use crate::core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{
+        self, ButtplugMessage, ButtplugMessageUnion, MessageAttributes, RotationSubcommand,
+        VibrateSubcommand,
+    },
+};
+use async_std::future;
+use async_trait::async_trait;
+use std::{collections::HashMap, time::Duration};
+
+/// How long to wait for the reply to `DeviceType;`.
+const DEVICE_TYPE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Model capabilities are detected during initialization, and decide which
+/// messages are advertised and accepted. Devices that don't say what model
+/// they are get treated as a single vibrator.
+pub struct LovenseProtocol {
+    model: LovenseModel,
+    vibration: VibrateSpeeds,
+    /// Last step written to each motor, [None] until the first write.
+    vibrate_steps: Vec<Option<u32>>,
+    rotation: RotationState,
+}
+
+impl Default for LovenseProtocol {
+    fn default() -> Self {
+        LovenseProtocol::new(lovense_model("Lovense Device", 1, 0))
+    }
+}
+
+impl LovenseProtocol {
+    fn new(model: LovenseModel) -> Self {
+        Self {
+            vibration: VibrateSpeeds::new(model.vibrator_count),
+            vibrate_steps: vec![None; model.vibrator_count as usize],
+            rotation: RotationState::new(model.rotator_count),
+            model,
+        }
+    }
+}
+
+impl LovenseProtocol {
+    /// Multi motor commands address each motor, so motors missing from
+    /// `speeds` are left running at their last speed. Motors that are
+    /// already at the requested step are left out of the write, and if none
+    /// of them change, nothing is written.
+    async fn handle_vibrate_cmd(
+        &mut self,
+        device: &dyn DeviceImpl,
+        speeds: &[VibrateSubcommand],
+    ) -> Result<(), ButtplugError> {
+        let step_count = self.model.step_count;
+        let vibrate_steps = &self.vibrate_steps;
+        let changed: Vec<VibrateSubcommand> = speeds
+            .iter()
+            .filter(|subcommand| {
+                vibrate_steps.get(subcommand.index as usize)
+                    != Some(&Some(speed_to_step(subcommand.speed, step_count)))
+            })
+            .cloned()
+            .collect();
+        if !changed.is_empty() || speeds.is_empty() {
+            let command = lovense_vibrate_bytes(&changed, self.model.vibrator_count, step_count)?;
+            write_with_retry(device, Endpoint::Tx, &command).await?;
+            for subcommand in &changed {
+                self.vibrate_steps[subcommand.index as usize] =
+                    Some(speed_to_step(subcommand.speed, step_count));
+            }
+        }
+        self.vibration.update(speeds)?;
+        Ok(())
+    }
+
+    /// Lovense rotators can only be told to reverse, not which way to turn,
+    /// so direction changes are tracked to know when to send
+    /// `RotateChange;`.
+    async fn handle_rotate_cmd(
+        &mut self,
+        device: &dyn DeviceImpl,
+        rotations: &[RotationSubcommand],
+    ) -> Result<(), ButtplugError> {
+        // Every rotating model has a single rotator.
+        let rotation = match rotations {
+            [rotation] if rotation.index == 0 => rotation,
+            _ => {
+                return Err(ButtplugDeviceError::new(&format!(
+                    "{} takes a single rotation for index 0.",
+                    self.model.name
+                ))
+                .into())
+            }
+        };
+        // Only keep the new direction once the device has been told.
+        let mut state = self.rotation.clone();
+        if !state.update(rotations)?.is_empty() {
+            write_with_retry(device, Endpoint::Tx, b"RotateChange;").await?;
+        }
+        let speed = speed_to_step(rotation.speed, self.model.step_count);
+        write_with_retry(
+            device,
+            Endpoint::Tx,
+            format!("Rotate:{};", speed).as_bytes(),
+        )
+        .await?;
+        self.rotation = state;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ButtplugProtocol for LovenseProtocol {
+    /// Asks the device for its model. Replies look like
+    /// `Z:11:0082059AD3BD;`, model letter(s), firmware version and device
+    /// id.
+    async fn initialize(&mut self, device: &dyn DeviceImpl) -> Result<(), ButtplugError> {
+        let receiver = device.receiver();
+        write_with_retry(device, Endpoint::Tx, b"DeviceType;").await?;
+        let reply = match future::timeout(DEVICE_TYPE_TIMEOUT, receiver.recv()).await {
+            Ok(Some(reply)) => reply,
+            _ => {
+                warn!(
+                    "{} did not reply to DeviceType, assuming a single vibrator.",
+                    device.name()
+                );
+                return Ok(());
+            }
+        };
+        let reply = String::from_utf8_lossy(&reply);
+        let device_type = reply.trim_end_matches(';').split(':').next().unwrap_or("");
+        match lovense_model_for_type(device_type) {
+            Some(model) => {
+                info!("{} is a Lovense {}.", device.name(), model.name);
+                *self = LovenseProtocol::new(model);
+            }
+            None => warn!(
+                "{} replied with unknown device type {}, assuming a single vibrator.",
+                device.name(),
+                reply
+            ),
+        }
+        Ok(())
+    }
+
+    fn device_messages(&self) -> HashMap<String, MessageAttributes> {
+        self.model.device_messages()
+    }
+
+    /// Zeroes every motor in one write. Rotators are stopped without
+    /// touching their direction. The step cache is reset first, so the stop
+    /// is always written, even if every motor was last set to 0.
+    async fn stop_device(
+        &mut self,
+        device: &dyn DeviceImpl,
+        _msg: &messages::StopDeviceCmd,
+    ) -> Result<(), ButtplugError> {
+        self.vibrate_steps = vec![None; self.model.vibrator_count as usize];
+        let speeds: Vec<VibrateSubcommand> = (0..self.model.vibrator_count)
+            .map(|index| VibrateSubcommand::new(index, 0.0))
+            .collect();
+        self.handle_vibrate_cmd(device, &speeds).await?;
+        if self.model.rotator_count > 0 {
+            write_with_retry(device, Endpoint::Tx, b"Rotate:0;").await?;
+        }
+        Ok(())
+    }
+
+    async fn parse_message(
+        &mut self,
+        device: &dyn DeviceImpl,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError> {
+        match msg {
+            ButtplugMessageUnion::VibrateCmd(ref m) => {
+                self.handle_vibrate_cmd(device, &m.speeds).await?
+            }
+            ButtplugMessageUnion::RotateCmd(ref m) if self.model.rotator_count > 0 => {
+                self.handle_rotate_cmd(device, &m.rotations).await?
+            }
+            _ => {
+                return Err(ButtplugDeviceError::new(&format!(
+                    "{} does not support {}.",
+                    self.model.name,
+                    msg.name()
+                ))
+                .into())
+            }
+        }
+        Ok(messages::Ok::new(msg.get_id()).as_union())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::devices::test_device::TestDeviceImpl;
+    use async_std::task;
+
+    fn vibrate_cmd(speeds: Vec<VibrateSubcommand>) -> ButtplugMessageUnion {
+        messages::VibrateCmd::new(0, speeds).as_union()
+    }
+
+    #[test]
+    fn test_lovense_vibrate_cmd_single_motor() {
+        let device = TestDeviceImpl::new("LVS-Hush", "test");
+        let mut protocol = LovenseProtocol::default();
+        task::block_on(async {
+            let msg = vibrate_cmd(vec![VibrateSubcommand::new(0, 0.5)]);
+            assert_eq!(
+                protocol.parse_message(&device, &msg).await.unwrap(),
+                messages::Ok::new(msg.get_id()).as_union()
+            );
+            protocol
+                .parse_message(&device, &vibrate_cmd(vec![VibrateSubcommand::new(0, 1.0)]))
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"Vibrate:10;".to_vec()),
+                    (Endpoint::Tx, b"Vibrate:20;".to_vec())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_vibrate_cmd_dual_motor() {
+        let device = TestDeviceImpl::new("LVS-Edge", "test");
+        let mut protocol = LovenseProtocol::new(lovense_model_for_type("P").unwrap());
+        task::block_on(async {
+            protocol
+                .parse_message(
+                    &device,
+                    &vibrate_cmd(vec![
+                        VibrateSubcommand::new(0, 0.25),
+                        VibrateSubcommand::new(1, 0.75),
+                    ]),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![(Endpoint::Tx, b"Vibrate1:5;Vibrate2:15;".to_vec())]
+            );
+            let err = protocol
+                .parse_message(&device, &vibrate_cmd(vec![VibrateSubcommand::new(2, 0.5)]))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorDevice
+            );
+            assert!(device.take_written().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_lovense_vibrate_cmd_gap_keeps_previous_speed() {
+        let device = TestDeviceImpl::new("LVS-Test", "test");
+        let mut protocol = LovenseProtocol::new(lovense_model("Test", 3, 0));
+        task::block_on(async {
+            protocol
+                .parse_message(
+                    &device,
+                    &vibrate_cmd(vec![
+                        VibrateSubcommand::new(0, 0.25),
+                        VibrateSubcommand::new(1, 0.5),
+                        VibrateSubcommand::new(2, 0.75),
+                    ]),
+                )
+                .await
+                .unwrap();
+            protocol
+                .parse_message(
+                    &device,
+                    &vibrate_cmd(vec![
+                        VibrateSubcommand::new(0, 1.0),
+                        VibrateSubcommand::new(2, 0.0),
+                    ]),
+                )
+                .await
+                .unwrap();
+            assert_eq!(protocol.vibration.speeds(), &[1.0, 0.5, 0.0]);
+            // Motor 1 isn't addressed, so it keeps running.
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (
+                        Endpoint::Tx,
+                        b"Vibrate1:5;Vibrate2:10;Vibrate3:15;".to_vec()
+                    ),
+                    (Endpoint::Tx, b"Vibrate1:20;Vibrate3:0;".to_vec())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_vibrate_cmd_skips_unchanged_steps() {
+        let device = TestDeviceImpl::new("LVS-Edge", "test");
+        let mut protocol = LovenseProtocol::new(lovense_model_for_type("P").unwrap());
+        task::block_on(async {
+            for speeds in [
+                vec![
+                    VibrateSubcommand::new(0, 0.02),
+                    VibrateSubcommand::new(1, 0.5),
+                ],
+                // Same steps as the first command.
+                vec![
+                    VibrateSubcommand::new(0, 0.04),
+                    VibrateSubcommand::new(1, 0.5),
+                ],
+                vec![
+                    VibrateSubcommand::new(0, 0.04),
+                    VibrateSubcommand::new(1, 0.75),
+                ],
+            ]
+            .iter()
+            {
+                protocol
+                    .parse_message(&device, &vibrate_cmd(speeds.clone()))
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"Vibrate1:1;Vibrate2:10;".to_vec()),
+                    (Endpoint::Tx, b"Vibrate2:15;".to_vec())
+                ]
+            );
+            assert_eq!(protocol.vibration.speeds(), &[0.04, 0.75]);
+        });
+    }
+
+    #[test]
+    fn test_lovense_vibrate_cmd_dedupe() {
+        let device = TestDeviceImpl::new("LVS-Hush", "test");
+        let mut protocol = LovenseProtocol::default();
+        let stop_cmd = messages::StopDeviceCmd::new(0);
+        task::block_on(async {
+            for _ in 0..2 {
+                let msg = vibrate_cmd(vec![VibrateSubcommand::new(0, 0.5)]);
+                assert_eq!(
+                    protocol.parse_message(&device, &msg).await.unwrap(),
+                    messages::Ok::new(msg.get_id()).as_union()
+                );
+            }
+            assert_eq!(
+                device.take_written(),
+                vec![(Endpoint::Tx, b"Vibrate:10;".to_vec())]
+            );
+            // Stops reset the cache, so they always get written.
+            protocol.stop_device(&device, &stop_cmd).await.unwrap();
+            protocol.stop_device(&device, &stop_cmd).await.unwrap();
+            protocol
+                .parse_message(&device, &vibrate_cmd(vec![VibrateSubcommand::new(0, 0.0)]))
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"Vibrate:0;".to_vec()),
+                    (Endpoint::Tx, b"Vibrate:0;".to_vec())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_vibrate_cmd_retries_failed_write() {
+        let device = TestDeviceImpl::new("LVS-Hush", "test");
+        let mut protocol = LovenseProtocol::default();
+        task::block_on(async {
+            device.fail_writes(1);
+            let msg = vibrate_cmd(vec![VibrateSubcommand::new(0, 0.5)]);
+            assert_eq!(
+                protocol.parse_message(&device, &msg).await.unwrap(),
+                messages::Ok::new(msg.get_id()).as_union()
+            );
+            assert_eq!(
+                device.take_written(),
+                vec![(Endpoint::Tx, b"Vibrate:10;".to_vec())]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_stop_device_cmd() {
+        let device = TestDeviceImpl::new("LVS-Edge", "test");
+        let mut protocol = LovenseProtocol::new(lovense_model_for_type("P").unwrap());
+        task::block_on(async {
+            protocol
+                .stop_device(&device, &messages::StopDeviceCmd::new(0))
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![(Endpoint::Tx, b"Vibrate1:0;Vibrate2:0;".to_vec())]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_initialize_device_type() {
+        let device = TestDeviceImpl::new("LVS-Edge", "test");
+        let mut protocol = LovenseProtocol::default();
+        task::block_on(async {
+            device.add_reply(b"P:37:C44F33D0AB2C;").await;
+            protocol.initialize(&device).await.unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![(Endpoint::Tx, b"DeviceType;".to_vec())]
+            );
+            assert_eq!(protocol.model, lovense_model_for_type("P").unwrap());
+            assert_eq!(
+                protocol.device_messages()["VibrateCmd"].feature_count,
+                Some(2)
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_initialize_no_reply() {
+        let device = TestDeviceImpl::new("LVS-Unknown", "test");
+        let mut protocol = LovenseProtocol::default();
+        task::block_on(async {
+            protocol.initialize(&device).await.unwrap();
+            assert_eq!(protocol.model.vibrator_count, 1);
+            protocol
+                .parse_message(&device, &vibrate_cmd(vec![VibrateSubcommand::new(0, 0.5)]))
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"DeviceType;".to_vec()),
+                    (Endpoint::Tx, b"Vibrate:10;".to_vec())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_model_routing() {
+        let device = TestDeviceImpl::new("LVS-Nora", "test");
+        let mut nora = LovenseProtocol::new(lovense_model_for_type("A").unwrap());
+        let rotate_cmd = |speed, clockwise| {
+            messages::RotateCmd::new(0, vec![RotationSubcommand::new(0, speed, clockwise)])
+                .as_union()
+        };
+        task::block_on(async {
+            nora.parse_message(&device, &rotate_cmd(0.5, true))
+                .await
+                .unwrap();
+            nora.parse_message(&device, &rotate_cmd(1.0, false))
+                .await
+                .unwrap();
+            nora.stop_device(&device, &messages::StopDeviceCmd::new(0))
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"Rotate:10;".to_vec()),
+                    (Endpoint::Tx, b"RotateChange;".to_vec()),
+                    (Endpoint::Tx, b"Rotate:20;".to_vec()),
+                    (Endpoint::Tx, b"Vibrate:0;".to_vec()),
+                    (Endpoint::Tx, b"Rotate:0;".to_vec())
+                ]
+            );
+
+            let mut hush = LovenseProtocol::new(lovense_model_for_type("Z").unwrap());
+            let err = hush
+                .parse_message(&device, &rotate_cmd(0.5, true))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorDevice
+            );
+            assert!(device.take_written().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_lovense_rotate_cmd_directions() {
+        let device = TestDeviceImpl::new("LVS-Nora", "test");
+        let mut nora = LovenseProtocol::new(lovense_model_for_type("A").unwrap());
+        let rotate_cmd = |speed, clockwise| {
+            messages::RotateCmd::new(0, vec![RotationSubcommand::new(0, speed, clockwise)])
+                .as_union()
+        };
+        task::block_on(async {
+            // Rotators start clockwise, so the first counterclockwise
+            // command reverses.
+            nora.parse_message(&device, &rotate_cmd(0.25, false))
+                .await
+                .unwrap();
+            nora.parse_message(&device, &rotate_cmd(0.75, false))
+                .await
+                .unwrap();
+            nora.parse_message(&device, &rotate_cmd(0.75, true))
+                .await
+                .unwrap();
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"RotateChange;".to_vec()),
+                    (Endpoint::Tx, b"Rotate:5;".to_vec()),
+                    (Endpoint::Tx, b"Rotate:15;".to_vec()),
+                    (Endpoint::Tx, b"RotateChange;".to_vec()),
+                    (Endpoint::Tx, b"Rotate:15;".to_vec())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_lovense_rotate_cmd_failed_write_keeps_direction() {
+        let device = TestDeviceImpl::new("LVS-Nora", "test");
+        let mut nora = LovenseProtocol::new(lovense_model_for_type("A").unwrap());
+        let rotate_cmd = |clockwise| {
+            messages::RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, clockwise)]).as_union()
+        };
+        task::block_on(async {
+            // Fail the write and its retry.
+            device.fail_writes(2);
+            assert!(nora
+                .parse_message(&device, &rotate_cmd(false))
+                .await
+                .is_err());
+            assert_eq!(nora.rotation.is_clockwise(0), Some(true));
+            // The direction change is sent again on the next try.
+            nora.parse_message(&device, &rotate_cmd(false))
+                .await
+                .unwrap();
+            assert_eq!(nora.rotation.is_clockwise(0), Some(false));
+            assert_eq!(
+                device.take_written(),
+                vec![
+                    (Endpoint::Tx, b"RotateChange;".to_vec()),
+                    (Endpoint::Tx, b"Rotate:10;".to_vec())
+                ]
+            );
+        });
+    }
+}
@@ -6,3 +6,464 @@
 // for full license information.
 
 //! Device specific identification and protocol implementations.
+
+pub mod device;
+pub mod lovense;
+#[cfg(test)]
+pub(crate) mod test_device;
+
+use crate::core::{
+    errors::ButtplugDeviceError,
+    messages::{
+        ButtplugMessageType, MessageAttributes, RotateCmd, RotationSubcommand, StopDeviceCmd,
+        VibrateCmd, VibrateSubcommand,
+    },
+};
+use std::collections::HashMap;
+
+/// Quantizes a normalized (0.0-1.0) speed to one of a device's discrete
+/// steps.
+///
+/// Speeds are rounded up, so any non-zero speed maps to at least step 1 and
+/// doesn't turn the device off. Speeds that map to the same step produce
+/// the same device command, so callers can compare steps to skip redundant
+/// writes.
+pub fn speed_to_step(speed: f64, step_count: u32) -> u32 {
+    let steps = speed.clamp(0.0, 1.0) * f64::from(step_count);
+    // Don't let float error push an exact step up to the next one.
+    let rounded = steps.round();
+    if (steps - rounded).abs() < 1e-9 {
+        rounded as u32
+    } else {
+        steps.ceil() as u32
+    }
+}
+
+/// Builds the Lovense command bytes for a set of vibrate speeds.
+///
+/// Lovense speeds are integers in the 0-`step_count` range. Single motor
+/// devices take `Vibrate:x;`, multi motor devices take `Vibrate1:x;`,
+/// `Vibrate2:x;` etc., one per addressed motor, concatenated in subcommand
+/// order. Speeds outside of 0.0-1.0 are clamped.
+pub fn lovense_vibrate_bytes(
+    speeds: &[VibrateSubcommand],
+    motor_count: u32,
+    step_count: u32,
+) -> Result<Vec<u8>, ButtplugDeviceError> {
+    if speeds.is_empty() {
+        return Err(ButtplugDeviceError::new("No vibrate speeds given."));
+    }
+    let mut command = String::new();
+    for subcommand in speeds {
+        if subcommand.index >= motor_count {
+            return Err(ButtplugDeviceError::new(&format!(
+                "Motor index {} out of range, device has {} motors.",
+                subcommand.index, motor_count
+            )));
+        }
+        let speed = speed_to_step(subcommand.speed, step_count);
+        if motor_count == 1 {
+            command += &format!("Vibrate:{};", speed);
+        } else {
+            command += &format!("Vibrate{}:{};", subcommand.index + 1, speed);
+        }
+    }
+    Ok(command.into_bytes())
+}
+
+/// Capabilities of a Lovense model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LovenseModel {
+    pub name: &'static str,
+    pub vibrator_count: u32,
+    pub rotator_count: u32,
+    /// Number of speed steps above off. Every current model takes 0-20.
+    pub step_count: u32,
+}
+
+impl LovenseModel {
+    /// Messages to advertise in DeviceAdded for this model.
+    pub fn device_messages(&self) -> HashMap<String, MessageAttributes> {
+        let mut device_messages = HashMap::new();
+        let mut add = |message_name: &str, feature_count: Option<u32>| {
+            device_messages.insert(
+                message_name.to_owned(),
+                MessageAttributes {
+                    feature_count,
+                    patterns: None,
+                },
+            );
+        };
+        add(StopDeviceCmd::MESSAGE_NAME, None);
+        if self.vibrator_count > 0 {
+            add(VibrateCmd::MESSAGE_NAME, Some(self.vibrator_count));
+        }
+        if self.rotator_count > 0 {
+            add(RotateCmd::MESSAGE_NAME, Some(self.rotator_count));
+        }
+        device_messages
+    }
+}
+
+fn lovense_model(name: &'static str, vibrator_count: u32, rotator_count: u32) -> LovenseModel {
+    LovenseModel {
+        name,
+        vibrator_count,
+        rotator_count,
+        step_count: 20,
+    }
+}
+
+/// Looks up a Lovense model by the device type letter(s) the device reports
+/// as the first field of its `DeviceType;` reply (`Z:11:0082059AD3BD;` for a
+/// Hush, for instance). Returns [None] for unknown models.
+pub fn lovense_model_for_type(device_type: &str) -> Option<LovenseModel> {
+    let model = match device_type {
+        "A" | "C" => lovense_model("Nora", 1, 1),
+        "B" => lovense_model("Max", 1, 0),
+        "L" => lovense_model("Ambi", 1, 0),
+        "P" => lovense_model("Edge", 2, 0),
+        "S" => lovense_model("Lush", 1, 0),
+        "W" => lovense_model("Domi", 1, 0),
+        "Z" => lovense_model("Hush", 1, 0),
+        _ => return None,
+    };
+    Some(model)
+}
+
+/// Tracks the last speed set on each of a device's vibration motors.
+///
+/// VibrateCmd only needs to address the motors it changes. Motors missing
+/// from a command keep their last speed, so a command for indexes 0 and 2
+/// leaves motor 1 as it was. Protocols that have to send every motor's
+/// speed in each write can use this to fill in the gaps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VibrateSpeeds {
+    speeds: Vec<f64>,
+}
+
+impl VibrateSpeeds {
+    /// Starts with all motors stopped.
+    pub fn new(motor_count: u32) -> Self {
+        Self {
+            speeds: vec![0.0; motor_count as usize],
+        }
+    }
+
+    /// Applies the subcommands and returns the speed of every motor
+    /// afterwards. If any index is out of range, nothing is changed.
+    pub fn update(&mut self, speeds: &[VibrateSubcommand]) -> Result<&[f64], ButtplugDeviceError> {
+        if let Some(subcommand) = speeds
+            .iter()
+            .find(|subcommand| subcommand.index as usize >= self.speeds.len())
+        {
+            return Err(ButtplugDeviceError::new(&format!(
+                "Motor index {} out of range, device has {} motors.",
+                subcommand.index,
+                self.speeds.len()
+            )));
+        }
+        for subcommand in speeds {
+            self.speeds[subcommand.index as usize] = subcommand.speed;
+        }
+        Ok(&self.speeds)
+    }
+
+    /// Current speed of every motor, by index.
+    pub fn speeds(&self) -> &[f64] {
+        &self.speeds
+    }
+}
+
+/// Tracks the position of a linear actuator, for protocols whose devices
+/// take a speed instead of a duration.
+///
+/// The FleshlightLaunchFW12 speed byte depends on how far the device has to
+/// move, which LinearCmd doesn't say, so protocols like Kiiroo/Launch keep
+/// one of these per axis. Devices are assumed to start at position 0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinearPosition {
+    position: f64,
+}
+
+impl LinearPosition {
+    /// Converts a move to a normalized (0.0-1.0) position over a duration in
+    /// milliseconds into a FleshlightLaunchFW12 (position, speed) byte pair,
+    /// and records the new position. Speed is derived from the
+    /// distance/duration relation documented for the Launch firmware:
+    ///
+    /// `speed = 25000 * (duration * 90 / distance)^-1.05`
+    ///
+    /// with distance in percent of the full stroke. Both bytes are clamped to
+    /// the 0-99 range the firmware accepts.
+    pub fn linear_to_fleshlight(&mut self, position: f64, duration: u32) -> (u8, u8) {
+        let position = position.clamp(0.0, 1.0);
+        let distance = (position - self.position).abs() * 100.0;
+        self.position = position;
+        let position_byte = (position * 99.0).round() as u8;
+        if distance == 0.0 {
+            return (position_byte, 0);
+        }
+        let duration = f64::from(duration.max(1));
+        let speed = 25000.0 * (duration * 90.0 / distance).powf(-1.05);
+        (position_byte, speed.clamp(0.0, 99.0) as u8)
+    }
+
+    /// Last position moved to.
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+}
+
+/// Tracks the rotation direction of each of a device's rotators.
+///
+/// Some devices (Lovense Nora, for instance) can't be told a direction, only
+/// to reverse the current one. RotateCmd carries an absolute direction, so
+/// protocols for those devices need to remember which way each rotator is
+/// turning to know when to send a toggle. Rotators start out clockwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationState {
+    clockwise: Vec<bool>,
+}
+
+impl RotationState {
+    pub fn new(rotator_count: u32) -> Self {
+        Self {
+            clockwise: vec![true; rotator_count as usize],
+        }
+    }
+
+    /// Applies the subcommands and returns the indexes of rotators whose
+    /// direction changed, and so need a toggle command. If any index is out
+    /// of range, nothing is changed.
+    pub fn update(
+        &mut self,
+        rotations: &[RotationSubcommand],
+    ) -> Result<Vec<u32>, ButtplugDeviceError> {
+        if let Some(subcommand) = rotations
+            .iter()
+            .find(|subcommand| subcommand.index as usize >= self.clockwise.len())
+        {
+            return Err(ButtplugDeviceError::new(&format!(
+                "Rotator index {} out of range, device has {} rotators.",
+                subcommand.index,
+                self.clockwise.len()
+            )));
+        }
+        let mut toggled = vec![];
+        for subcommand in rotations {
+            let clockwise = &mut self.clockwise[subcommand.index as usize];
+            if *clockwise != subcommand.clockwise {
+                *clockwise = subcommand.clockwise;
+                toggled.push(subcommand.index);
+            }
+        }
+        Ok(toggled)
+    }
+
+    /// True if the rotator at the given index is turning clockwise.
+    pub fn is_clockwise(&self, index: u32) -> Option<bool> {
+        self.clockwise.get(index as usize).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        lovense_model_for_type, lovense_vibrate_bytes, speed_to_step, LinearPosition,
+        RotationState, VibrateSpeeds,
+    };
+    use crate::core::messages::{RotationSubcommand, VibrateSubcommand};
+
+    #[test]
+    fn test_linear_to_fleshlight_fast_short_move() {
+        let mut linear = LinearPosition::default();
+        assert_eq!(linear.linear_to_fleshlight(0.1, 50), (10, 40));
+        assert_eq!(linear.position(), 0.1);
+    }
+
+    #[test]
+    fn test_linear_to_fleshlight_slow_long_move() {
+        let mut linear = LinearPosition::default();
+        assert_eq!(linear.linear_to_fleshlight(1.0, 2000), (99, 9));
+        // The distance back comes from the tracked position.
+        assert_eq!(linear.linear_to_fleshlight(0.0, 2000), (0, 9));
+    }
+
+    #[test]
+    fn test_linear_to_fleshlight_clamping() {
+        let mut linear = LinearPosition::default();
+        // Faster than the device can move.
+        assert_eq!(linear.linear_to_fleshlight(1.0, 100), (99, 99));
+        // No movement.
+        assert_eq!(linear.linear_to_fleshlight(1.0, 500), (99, 0));
+        assert_eq!(linear.linear_to_fleshlight(2.0, 2000), (99, 0));
+        assert_eq!(linear.linear_to_fleshlight(-1.0, 2000), (0, 9));
+    }
+
+    #[test]
+    fn test_speed_to_step() {
+        assert_eq!(speed_to_step(0.02, 20), 1);
+        assert_eq!(speed_to_step(0.04, 20), 1);
+        assert_eq!(speed_to_step(0.0, 20), 0);
+        assert_eq!(speed_to_step(0.15, 20), 3);
+        assert_eq!(speed_to_step(0.16, 20), 4);
+        assert_eq!(speed_to_step(1.0, 20), 20);
+        assert_eq!(speed_to_step(2.0, 20), 20);
+    }
+
+    #[test]
+    fn test_lovense_vibrate_bytes_single_motor() {
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, 0.5)], 1, 20).unwrap(),
+            b"Vibrate:10;".to_vec()
+        );
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, 0.0)], 1, 20).unwrap(),
+            b"Vibrate:0;".to_vec()
+        );
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, 0.02)], 1, 20).unwrap(),
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, 0.04)], 1, 20).unwrap()
+        );
+        assert!(lovense_vibrate_bytes(&[VibrateSubcommand::new(1, 0.5)], 1, 20).is_err());
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, 1.5)], 1, 20).unwrap(),
+            b"Vibrate:20;".to_vec()
+        );
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, -0.5)], 1, 20).unwrap(),
+            b"Vibrate:0;".to_vec()
+        );
+        assert!(lovense_vibrate_bytes(&[], 1, 20).is_err());
+        // Steps follow the model, not a fixed range.
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(0, 0.5)], 1, 10).unwrap(),
+            b"Vibrate:5;".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_lovense_vibrate_bytes_dual_motor() {
+        assert_eq!(
+            lovense_vibrate_bytes(
+                &[
+                    VibrateSubcommand::new(0, 1.0),
+                    VibrateSubcommand::new(1, 0.25)
+                ],
+                2,
+                20
+            )
+            .unwrap(),
+            b"Vibrate1:20;Vibrate2:5;".to_vec()
+        );
+        assert_eq!(
+            lovense_vibrate_bytes(&[VibrateSubcommand::new(1, 0.5)], 2, 20).unwrap(),
+            b"Vibrate2:10;".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_vibrate_speeds_gap_keeps_previous_speed() {
+        let mut speeds = VibrateSpeeds::new(3);
+        speeds
+            .update(&[
+                VibrateSubcommand::new(0, 0.2),
+                VibrateSubcommand::new(1, 0.4),
+                VibrateSubcommand::new(2, 0.6),
+            ])
+            .unwrap();
+        assert_eq!(
+            speeds
+                .update(&[
+                    VibrateSubcommand::new(0, 1.0),
+                    VibrateSubcommand::new(2, 0.0)
+                ])
+                .unwrap(),
+            &[1.0, 0.4, 0.0]
+        );
+        assert!(speeds.update(&[VibrateSubcommand::new(3, 0.5)]).is_err());
+        assert_eq!(speeds.speeds(), &[1.0, 0.4, 0.0]);
+    }
+
+    #[test]
+    fn test_rotation_state_same_direction() {
+        let mut state = RotationState::new(1);
+        assert!(state
+            .update(&[RotationSubcommand::new(0, 0.5, true)])
+            .unwrap()
+            .is_empty());
+        assert!(state
+            .update(&[RotationSubcommand::new(0, 1.0, true)])
+            .unwrap()
+            .is_empty());
+        assert_eq!(state.is_clockwise(0), Some(true));
+    }
+
+    #[test]
+    fn test_rotation_state_direction_flip() {
+        let mut state = RotationState::new(2);
+        assert_eq!(
+            state
+                .update(&[
+                    RotationSubcommand::new(0, 0.5, true),
+                    RotationSubcommand::new(1, 0.5, false)
+                ])
+                .unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            state
+                .update(&[RotationSubcommand::new(1, 0.5, true)])
+                .unwrap(),
+            vec![1]
+        );
+        assert!(state
+            .update(&[RotationSubcommand::new(2, 0.5, false)])
+            .is_err());
+        assert_eq!(state.is_clockwise(1), Some(true));
+        assert_eq!(state.is_clockwise(2), None);
+    }
+
+    #[test]
+    fn test_lovense_model_capabilities() {
+        let nora = lovense_model_for_type("A").unwrap();
+        assert_eq!(nora.name, "Nora");
+        assert_eq!(lovense_model_for_type("C"), Some(nora.clone()));
+        let messages = nora.device_messages();
+        assert_eq!(messages["VibrateCmd"].feature_count, Some(1));
+        assert_eq!(messages["RotateCmd"].feature_count, Some(1));
+        assert!(messages.contains_key("StopDeviceCmd"));
+
+        let edge = lovense_model_for_type("P").unwrap();
+        assert_eq!(edge.name, "Edge");
+        assert_eq!(edge.device_messages()["VibrateCmd"].feature_count, Some(2));
+        assert!(!edge.device_messages().contains_key("RotateCmd"));
+        // Edge commands address each motor.
+        assert_eq!(
+            lovense_vibrate_bytes(
+                &[VibrateSubcommand::new(1, 0.5)],
+                edge.vibrator_count,
+                edge.step_count
+            )
+            .unwrap(),
+            b"Vibrate2:10;".to_vec()
+        );
+
+        let hush = lovense_model_for_type("Z").unwrap();
+        assert_eq!(hush.name, "Hush");
+        assert_eq!(hush.device_messages()["VibrateCmd"].feature_count, Some(1));
+        assert!(!hush.device_messages().contains_key("RotateCmd"));
+        assert_eq!(
+            lovense_vibrate_bytes(
+                &[VibrateSubcommand::new(0, 0.5)],
+                hush.vibrator_count,
+                hush.step_count
+            )
+            .unwrap(),
+            b"Vibrate:10;".to_vec()
+        );
+
+        assert_eq!(lovense_model_for_type("?"), None);
+    }
+}
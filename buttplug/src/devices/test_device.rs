@@ -0,0 +1,156 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! In memory [DeviceImpl] and [ButtplugProtocol] for protocol and server
+//! tests.
+
+use super::device::{ButtplugDevice, ButtplugProtocol, DeviceImpl, Endpoint};
+use crate::core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    messages::{self, ButtplugMessage, ButtplugMessageUnion, MessageAttributes},
+};
+use async_std::sync::{channel, Receiver, Sender};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Records everything written to it, and replies with whatever the test
+/// queues up.
+pub struct TestDeviceImpl {
+    name: String,
+    address: String,
+    written: Mutex<Vec<(Endpoint, Vec<u8>)>>,
+    failing_writes: Mutex<usize>,
+    reply_sender: Sender<Vec<u8>>,
+    reply_receiver: Receiver<Vec<u8>>,
+}
+
+impl TestDeviceImpl {
+    pub fn new(name: &str, address: &str) -> Self {
+        let (reply_sender, reply_receiver) = channel(256);
+        Self {
+            name: name.to_owned(),
+            address: address.to_owned(),
+            written: Mutex::new(vec![]),
+            failing_writes: Mutex::new(0),
+            reply_sender,
+            reply_receiver,
+        }
+    }
+
+    /// Queues data as if the device had sent it on [Endpoint::Rx].
+    pub async fn add_reply(&self, data: &[u8]) {
+        self.reply_sender.send(data.to_vec()).await;
+    }
+
+    /// Makes the next `count` writes fail. Failed writes aren't recorded.
+    pub fn fail_writes(&self, count: usize) {
+        *self.failing_writes.lock().unwrap() = count;
+    }
+
+    /// Returns everything written since the last call.
+    pub fn take_written(&self) -> Vec<(Endpoint, Vec<u8>)> {
+        self.written.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[async_trait]
+impl DeviceImpl for TestDeviceImpl {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+
+    async fn write_value(&self, endpoint: Endpoint, data: &[u8]) -> Result<(), ButtplugError> {
+        let mut failing_writes = self.failing_writes.lock().unwrap();
+        if *failing_writes > 0 {
+            *failing_writes -= 1;
+            return Err(ButtplugDeviceError::new("Test device write failed.").into());
+        }
+        self.written.lock().unwrap().push((endpoint, data.to_vec()));
+        Ok(())
+    }
+
+    fn receiver(&self) -> Receiver<Vec<u8>> {
+        self.reply_receiver.clone()
+    }
+}
+
+/// Advertises the given messages, and records every message it is handed,
+/// replying Ok, or with a device error if failing. Clones share their
+/// record.
+#[derive(Clone, Default)]
+pub struct TestProtocol {
+    device_messages: HashMap<String, MessageAttributes>,
+    received: Arc<Mutex<Vec<ButtplugMessageUnion>>>,
+    failing: bool,
+}
+
+impl TestProtocol {
+    pub fn new(device_messages: HashMap<String, MessageAttributes>) -> Self {
+        Self {
+            device_messages,
+            received: Arc::new(Mutex::new(vec![])),
+            failing: false,
+        }
+    }
+
+    /// Fails every message it is handed, after recording it.
+    pub fn failing(device_messages: HashMap<String, MessageAttributes>) -> Self {
+        Self {
+            failing: true,
+            ..TestProtocol::new(device_messages)
+        }
+    }
+
+    /// Returns the list messages are recorded to.
+    pub fn received(&self) -> Arc<Mutex<Vec<ButtplugMessageUnion>>> {
+        self.received.clone()
+    }
+}
+
+#[async_trait]
+impl ButtplugProtocol for TestProtocol {
+    async fn initialize(&mut self, _device: &dyn DeviceImpl) -> Result<(), ButtplugError> {
+        Ok(())
+    }
+
+    fn device_messages(&self) -> HashMap<String, MessageAttributes> {
+        self.device_messages.clone()
+    }
+
+    async fn parse_message(
+        &mut self,
+        _device: &dyn DeviceImpl,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError> {
+        self.received.lock().unwrap().push(msg.clone());
+        if self.failing {
+            return Err(ButtplugDeviceError::new("Test protocol failed.").into());
+        }
+        Ok(messages::Ok::new(msg.get_id()).as_union())
+    }
+}
+
+/// Builds a device using the given protocol on top of a [TestDeviceImpl].
+pub async fn test_device(
+    name: &str,
+    address: &str,
+    protocol: impl ButtplugProtocol + 'static,
+) -> ButtplugDevice {
+    ButtplugDevice::new(
+        Arc::new(TestDeviceImpl::new(name, address)),
+        Box::new(protocol),
+    )
+    .await
+    .unwrap()
+}
@@ -62,13 +62,23 @@
 //! | Feature | Other Features Used | Description |
 //! | --------- | ----------- | ----------- |
 //! |  `client` | None | Buttplug client implementation (in-process connection only) |
-//! | `server` | None | Buttplug server implementation (in-process connection only) |
-//! | `serialize_json` | None | Serde JSON serializer for Buttplug messages, needed for remote connectors |
+//! | `server` | `serialize_json` | Buttplug server implementation, JSON is used by its transports |
+//! | `serialize` | None | Serde derives for Buttplug messages, used by the serializer features |
+//! | `serialize_json` | `serialize` | Serde JSON serializer for Buttplug messages, needed for remote connectors |
+//! | `serialize_cbor` | `serialize` | Serde CBOR serializer for Buttplug messages, for compact binary transports |
+//! | `serialize_msgpack` | `serialize` | Serde MessagePack serializer for Buttplug messages, for compact binary transports |
+//! | `strict_parsing` | `serialize` | Reject messages containing unknown fields, useful for debugging clients |
 //! | `client-ws` | `client`,`serialize_json` | Websocket client connector, used to connect clients to remote servers |
 //! | `client-ws-ssl` | `client`,`serialize_json` | Websocket client connector with SSL capabilities |
 //!
 //! Default attributes are `client-ws` and `server`.
 //!
+//! JSON encoding and decoding ([core::messages::ButtplugMessage::as_protocol_json],
+//! [core::messages::ButtplugMessageUnion::try_from_json], etc...) only exists
+//! with `serialize_json`. If the compiler can't find those methods, enable
+//! `serialize_json` or a feature that uses it. Building with only `client`
+//! works, for in-process use without any serialization.
+//!
 //! ## Plans for the Future
 //!
 //! The next 2 goals are:
@@ -86,6 +96,16 @@
 //! implementation yet, but one we do, those will be marked "Help Wanted" in our
 //! [github issues](https://github.com/buttplugio/buttplug-rs/issues).
 
+// Cargo already turns serialize_json on for these, this just catches a
+// feature list being edited without it.
+#[cfg(all(
+    any(feature = "client-ws", feature = "client-ws-ssl"),
+    not(feature = "serialize_json")
+))]
+compile_error!("The websocket connector features require the serialize_json feature.");
+#[cfg(all(feature = "server", not(feature = "serialize_json")))]
+compile_error!("The server feature requires the serialize_json feature.");
+
 #[macro_use]
 extern crate buttplug_derive;
 #[macro_use]
@@ -95,4 +115,6 @@ extern crate log;
 pub mod client;
 pub mod core;
 #[cfg(feature = "server")]
+pub mod devices;
+#[cfg(feature = "server")]
 pub mod server;
@@ -0,0 +1,119 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Time source for the server's handshake and ping timers.
+
+use async_std::task;
+use futures::future::{BoxFuture, FutureExt};
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tells the server timers what time it is and wakes them at their
+/// deadlines. Times are measured from an arbitrary starting point, only
+/// differences between them matter.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since the clock's starting point.
+    fn now(&self) -> Duration;
+
+    /// Resolves once [Clock::now] has reached the deadline.
+    fn sleep_until(&self, deadline: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Wall clock time, used by servers unless told otherwise.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> BoxFuture<'static, ()> {
+        task::sleep(deadline.checked_sub(self.now()).unwrap_or_default()).boxed()
+    }
+}
+
+/// Clock that only moves when [ManualClock::advance] is called, so timer
+/// tests don't depend on real time. Clones share the same time.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct ManualClock {
+    state: Arc<Mutex<ManualClockState>>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct ManualClockState {
+    now: Duration,
+    sleepers: Vec<(Duration, futures::channel::oneshot::Sender<()>)>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// Moves time forward, waking everything sleeping until then.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+        let now = state.now;
+        let (woken, sleeping) = state
+            .sleepers
+            .drain(..)
+            .partition(|(deadline, _)| *deadline <= now);
+        state.sleepers = sleeping;
+        for (_, sender) in woken {
+            // The sleeper may have been dropped already.
+            let _ = sender.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> BoxFuture<'static, ()> {
+        let mut state = self.state.lock().unwrap();
+        if deadline <= state.now {
+            return futures::future::ready(()).boxed();
+        }
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        state.sleepers.push((deadline, sender));
+        receiver.map(|_| ()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_wakes_at_deadline() {
+        let clock = ManualClock::default();
+        let mut sleep = clock.sleep_until(Duration::from_millis(100));
+        clock.advance(Duration::from_millis(99));
+        assert!((&mut sleep).now_or_never().is_none());
+        clock.advance(Duration::from_millis(1));
+        assert!(sleep.now_or_never().is_some());
+        assert_eq!(clock.now(), Duration::from_millis(100));
+        assert!(clock
+            .sleep_until(Duration::from_millis(50))
+            .now_or_never()
+            .is_some());
+    }
+}
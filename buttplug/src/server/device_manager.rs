@@ -8,12 +8,800 @@
 //! Buttplug Device Manager, manages Device Subtype (Platform/Communication bus
 //! specific) Managers
 
-use crate::core::errors::ButtplugError;
+use crate::{
+    core::{
+        errors::{ButtplugDeviceError, ButtplugError},
+        messages::{
+            ButtplugMessage, ButtplugMessageUnion, DeviceAdded, DeviceList, DeviceMessageInfo,
+            DeviceRemoved, StopDeviceCmd,
+        },
+    },
+    devices::device::ButtplugDevice,
+};
+use async_std::{
+    sync::{channel, Sender},
+    task,
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, Future, FutureExt};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
-trait DeviceSubtypeManager {
-    fn start_scanning() -> Result<(), ButtplugError>;
-    fn stop_scanning() -> Result<(), ButtplugError>;
-    fn is_scanning() -> bool;
+/// Device changes reported by a [DeviceSubtypeManager].
+pub enum SubtypeManagerEvent {
+    /// A device was found. It is only connected if the [DeviceFilter]
+    /// allows it. The device address is used to give it a stable index
+    /// across reconnects.
+    DeviceFound(DiscoveredDevice),
+    /// The device with the given address disconnected.
+    DeviceLost(String),
 }
 
-// struct DeviceManager {}
+/// Connects a discovered device and initializes its protocol.
+pub type DeviceConnector =
+    Box<dyn FnOnce() -> BoxFuture<'static, Result<ButtplugDevice, ButtplugError>> + Send>;
+
+/// A device found while scanning, before anything has been written to it.
+pub struct DiscoveredDevice {
+    /// Platform specific address (BLE address, serial port, etc...).
+    pub address: String,
+    /// Advertised name.
+    pub name: String,
+    pub connector: DeviceConnector,
+}
+
+impl DiscoveredDevice {
+    pub fn new<F, Fut>(address: &str, name: &str, connect: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<ButtplugDevice, ButtplugError>> + Send + 'static,
+    {
+        Self {
+            address: address.to_owned(),
+            name: name.to_owned(),
+            connector: Box::new(move || connect().boxed()),
+        }
+    }
+}
+
+/// Finds devices on a single platform or communication bus (bluetooth,
+/// serial, etc...).
+#[async_trait]
+pub trait DeviceSubtypeManager: Send {
+    /// Starts scanning. Devices found should be sent to the given sender,
+    /// without connecting to them, until scanning is stopped. Devices that disconnect should be reported
+    /// on the same sender, whether or not scanning is still running.
+    async fn start_scanning(
+        &mut self,
+        device_sender: Sender<SubtypeManagerEvent>,
+    ) -> Result<(), ButtplugError>;
+    async fn stop_scanning(&mut self) -> Result<(), ButtplugError>;
+    fn is_scanning(&self) -> bool;
+}
+
+/// Name the server builder and other Buttplug implementations use for
+/// [DeviceSubtypeManager].
+pub use self::DeviceSubtypeManager as DeviceCommunicationManager;
+
+/// Restricts which discovered devices are connected.
+///
+/// Entries match a device's address or its name. Entries ending in `*` match
+/// any device name starting with the rest of the entry. Devices matching the
+/// deny list are always skipped. If the allow list is empty, all other
+/// devices are allowed, otherwise only devices matching it are.
+#[derive(Default, Clone, Debug)]
+pub struct DeviceFilter {
+    pub allow_list: Vec<String>,
+    pub deny_list: Vec<String>,
+}
+
+impl DeviceFilter {
+    pub fn is_allowed(&self, address: &str, name: &str) -> bool {
+        if self
+            .deny_list
+            .iter()
+            .any(|entry| DeviceFilter::entry_matches(entry, address, name))
+        {
+            return false;
+        }
+        self.allow_list.is_empty()
+            || self
+                .allow_list
+                .iter()
+                .any(|entry| DeviceFilter::entry_matches(entry, address, name))
+    }
+
+    fn entry_matches(entry: &str, address: &str, name: &str) -> bool {
+        if let Some(prefix) = entry.strip_suffix('*') {
+            name.starts_with(prefix)
+        } else {
+            entry == address || entry == name
+        }
+    }
+}
+
+/// Connected devices by index. Shared between the [DeviceManager], which
+/// adds and removes devices, and the server, which sends them messages.
+#[derive(Clone, Default)]
+pub struct DeviceRegistry {
+    devices: Arc<Mutex<HashMap<u32, Arc<ButtplugDevice>>>>,
+}
+
+impl DeviceRegistry {
+    /// Returns the device with the given index, if it is connected.
+    pub fn device(&self, device_index: u32) -> Option<Arc<ButtplugDevice>> {
+        self.devices.lock().unwrap().get(&device_index).cloned()
+    }
+
+    /// Sends StopDeviceCmd to every connected device, in index order.
+    /// Devices that fail to stop don't keep the rest from being stopped.
+    /// Once all devices have been tried, any failures are returned together
+    /// as a single device error.
+    pub async fn stop_all(&self) -> Result<(), ButtplugError> {
+        let mut devices: Vec<(u32, Arc<ButtplugDevice>)> = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(device_index, device)| (*device_index, device.clone()))
+            .collect();
+        devices.sort_by_key(|(device_index, _)| *device_index);
+        let mut failures = vec![];
+        for (device_index, device) in devices {
+            let msg = StopDeviceCmd::new(device_index).as_union();
+            if let Err(err) = device.parse_message(&msg).await {
+                error!("Failed to stop device {}: {}", device_index, err);
+                failures.push(format!("{} ({})", device_index, err));
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ButtplugDeviceError::new(&format!(
+                "Failed to stop devices: {}",
+                failures.join(", ")
+            ))
+            .into())
+        }
+    }
+
+    fn insert(&self, device_index: u32, device: ButtplugDevice) {
+        self.devices
+            .lock()
+            .unwrap()
+            .insert(device_index, Arc::new(device));
+    }
+
+    fn remove(&self, device_index: u32) {
+        self.devices.lock().unwrap().remove(&device_index);
+    }
+}
+
+/// Runs scanning across all registered [DeviceSubtypeManager]s, and turns
+/// the devices they find and lose into [DeviceAdded] and [DeviceRemoved]
+/// messages on the server event channel.
+pub struct DeviceManager {
+    subtype_managers: Vec<Box<dyn DeviceSubtypeManager>>,
+    device_sender: Sender<SubtypeManagerEvent>,
+    device_filter: Arc<Mutex<DeviceFilter>>,
+    device_aliases: Arc<Mutex<HashMap<String, String>>>,
+    devices: DeviceRegistry,
+}
+
+impl DeviceManager {
+    pub fn new(event_sender: Sender<ButtplugMessageUnion>) -> Self {
+        let (device_sender, device_receiver) = channel::<SubtypeManagerEvent>(256);
+        let device_filter = Arc::new(Mutex::new(DeviceFilter::default()));
+        let task_device_filter = device_filter.clone();
+        let device_aliases = Arc::new(Mutex::new(HashMap::new()));
+        let task_device_aliases = device_aliases.clone();
+        let devices = DeviceRegistry::default();
+        let task_devices = devices.clone();
+        task::spawn(async move {
+            let mut index_allocator = DeviceIndexAllocator::default();
+            while let Some(event) = device_receiver.recv().await {
+                let discovered = match event {
+                    SubtypeManagerEvent::DeviceFound(discovered) => discovered,
+                    SubtypeManagerEvent::DeviceLost(address) => {
+                        // Devices skipped by the filter never got an index.
+                        if let Some(device_index) = index_allocator.allocated_index(&address) {
+                            info!("Device {} lost, freeing index {}", address, device_index);
+                            index_allocator.free(device_index);
+                            task_devices.remove(device_index);
+                            event_sender
+                                .send(DeviceRemoved::new(device_index).as_union())
+                                .await;
+                        }
+                        continue;
+                    }
+                };
+                if !task_device_filter
+                    .lock()
+                    .unwrap()
+                    .is_allowed(&discovered.address, &discovered.name)
+                {
+                    info!(
+                        "Device {} ({}) found, but not allowed by device filter, skipping.",
+                        discovered.name, discovered.address
+                    );
+                    continue;
+                }
+                let device = match (discovered.connector)().await {
+                    Ok(device) => device,
+                    Err(err) => {
+                        error!(
+                            "Failed to connect to device {} ({}): {}",
+                            discovered.name, discovered.address, err
+                        );
+                        continue;
+                    }
+                };
+                let device_index = index_allocator.allocate(device.address());
+                info!(
+                    "Device {} ({}) found, assigning index {}",
+                    device.name(),
+                    device.address(),
+                    device_index
+                );
+                let device_name = task_device_aliases
+                    .lock()
+                    .unwrap()
+                    .get(device.address())
+                    .cloned()
+                    .unwrap_or_else(|| device.name().to_owned());
+                let device_added =
+                    DeviceAdded::new(device_index, &device_name, device.device_messages().clone());
+                // Make the device reachable before anyone hears about it.
+                task_devices.insert(device_index, device);
+                event_sender.send(device_added.as_union()).await;
+            }
+        });
+        Self {
+            subtype_managers: vec![],
+            device_sender,
+            device_filter,
+            device_aliases,
+            devices,
+        }
+    }
+
+    /// Returns the registry of connected devices.
+    pub fn devices(&self) -> DeviceRegistry {
+        self.devices.clone()
+    }
+
+    /// Sets the filter deciding which discovered devices are added. Applies
+    /// to devices found from then on.
+    pub fn set_device_filter(&self, device_filter: DeviceFilter) {
+        *self.device_filter.lock().unwrap() = device_filter;
+    }
+
+    /// Sets user chosen display names for devices, keyed by device address.
+    /// Aliased devices are added under their alias instead of their
+    /// advertised name. Applies to devices found from then on. The
+    /// [DeviceFilter] still matches against the advertised name.
+    pub fn set_device_aliases(&self, device_aliases: HashMap<String, String>) {
+        *self.device_aliases.lock().unwrap() = device_aliases;
+    }
+
+    pub fn add_subtype_manager(&mut self, subtype_manager: Box<dyn DeviceSubtypeManager>) {
+        self.subtype_managers.push(subtype_manager);
+    }
+
+    /// Starts scanning on all subtype managers at once. A manager failing to
+    /// start doesn't stop the others; an error is only returned if no
+    /// manager could start.
+    pub async fn start_scanning(&mut self) -> Result<(), ButtplugError> {
+        let device_sender = self.device_sender.clone();
+        let results = future::join_all(
+            self.subtype_managers
+                .iter_mut()
+                .map(|manager| manager.start_scanning(device_sender.clone())),
+        )
+        .await;
+        DeviceManager::merge_results(results)
+    }
+
+    /// Stops scanning on all subtype managers at once. Same error handling
+    /// as [DeviceManager::start_scanning].
+    pub async fn stop_scanning(&mut self) -> Result<(), ButtplugError> {
+        let results = future::join_all(
+            self.subtype_managers
+                .iter_mut()
+                .map(|manager| manager.stop_scanning()),
+        )
+        .await;
+        DeviceManager::merge_results(results)
+    }
+
+    /// True if any subtype manager is scanning.
+    pub fn is_scanning(&self) -> bool {
+        self.subtype_managers
+            .iter()
+            .any(|manager| manager.is_scanning())
+    }
+
+    fn merge_results(results: Vec<Result<(), ButtplugError>>) -> Result<(), ButtplugError> {
+        let succeeded = results.iter().any(|result| result.is_ok());
+        let mut first_error = None;
+        for error in results.into_iter().filter_map(|result| result.err()) {
+            error!("Device subtype manager failed: {}", error);
+            first_error.get_or_insert(error);
+        }
+        match first_error {
+            Some(error) if !succeeded => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps a snapshot of the connected devices, so [DeviceList] replies don't
+/// need to be rebuilt from device state on every RequestDeviceList.
+///
+/// The generation counter is bumped every time a device is added or removed,
+/// so callers can tell whether the list has changed since they last saw it.
+#[derive(Default)]
+pub struct DeviceListCache {
+    devices: BTreeMap<u32, DeviceMessageInfo>,
+    cached_list: Option<Vec<DeviceMessageInfo>>,
+    generation: u64,
+}
+
+impl DeviceListCache {
+    pub fn device_added(&mut self, msg: &DeviceAdded) {
+        self.devices
+            .insert(msg.device_index, DeviceMessageInfo::from(msg));
+        self.invalidate();
+    }
+
+    pub fn device_removed(&mut self, msg: &DeviceRemoved) {
+        if self.devices.remove(&msg.device_index).is_some() {
+            self.invalidate();
+        }
+    }
+
+    /// Returns the info for the device with the given index, if it exists.
+    pub fn device(&self, device_index: u32) -> Option<&DeviceMessageInfo> {
+        self.devices.get(&device_index)
+    }
+
+    /// Returns the current device list, using the given id for the message.
+    pub fn device_list(&mut self, id: u32) -> DeviceList {
+        let devices = &self.devices;
+        let cached = self
+            .cached_list
+            .get_or_insert_with(|| devices.values().cloned().collect());
+        let mut list = DeviceList::default();
+        list.devices = cached.clone();
+        list.set_id(id);
+        list
+    }
+
+    /// Returns the number of times the device list has changed.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn invalidate(&mut self) {
+        self.cached_list = None;
+        self.generation += 1;
+    }
+}
+
+/// Hands out device indexes for newly connected devices.
+///
+/// Indexes are unique among connected devices. Each index stays bound to
+/// the address of the device it was first given to, so a device that
+/// disconnects and reconnects gets its old index back, and fresh devices
+/// never take an index that belongs to a known device.
+#[derive(Default)]
+pub struct DeviceIndexAllocator {
+    addresses: HashMap<String, u32>,
+    in_use: HashSet<u32>,
+}
+
+impl DeviceIndexAllocator {
+    /// Returns the index for the device with the given address, reusing its
+    /// previous index if it has connected before.
+    pub fn allocate(&mut self, address: &str) -> u32 {
+        if let Some(&index) = self.addresses.get(address) {
+            if self.in_use.insert(index) {
+                return index;
+            }
+        }
+        let reserved: HashSet<u32> = self.addresses.values().cloned().collect();
+        let index = (0..)
+            .find(|i| !self.in_use.contains(i) && !reserved.contains(i))
+            .unwrap();
+        self.in_use.insert(index);
+        self.addresses.insert(address.to_owned(), index);
+        index
+    }
+
+    /// Frees an index when its device disconnects. The index stays reserved
+    /// for that device's address.
+    pub fn free(&mut self, index: u32) {
+        self.in_use.remove(&index);
+    }
+
+    pub fn is_allocated(&self, index: u32) -> bool {
+        self.in_use.contains(&index)
+    }
+
+    /// Returns the index of the device with the given address, if it is
+    /// currently connected.
+    pub fn allocated_index(&self, address: &str) -> Option<u32> {
+        self.addresses
+            .get(address)
+            .cloned()
+            .filter(|index| self.is_allocated(*index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        core::messages::{
+            self, ButtplugMessageType, MessageAttributes, VibrateCmd, VibrateSubcommand,
+        },
+        devices::{
+            device::{ButtplugProtocol, DeviceImpl, Endpoint},
+            lovense::LovenseProtocol,
+            test_device::{test_device, TestDeviceImpl, TestProtocol},
+        },
+    };
+
+    fn device_added(device_index: u32) -> DeviceAdded {
+        let mut msg = DeviceAdded::default();
+        msg.device_index = device_index;
+        msg.device_name = format!("Test Device {}", device_index);
+        msg
+    }
+
+    #[test]
+    fn test_device_list_cache_generation() {
+        let mut cache = DeviceListCache::default();
+        assert_eq!(cache.generation(), 0);
+        assert!(cache.device_list(1).devices.is_empty());
+        cache.device_added(&device_added(0));
+        cache.device_added(&device_added(1));
+        assert_eq!(cache.generation(), 2);
+        let list = cache.device_list(3);
+        assert_eq!(list.get_id(), 3);
+        assert_eq!(list.devices.len(), 2);
+        // Reading the list doesn't change it.
+        assert_eq!(cache.device_list(4).devices, list.devices);
+        assert_eq!(cache.generation(), 2);
+        let mut removed = DeviceRemoved::default();
+        removed.device_index = 0;
+        cache.device_removed(&removed);
+        assert_eq!(cache.generation(), 3);
+        assert_eq!(cache.device_list(5).devices[0].device_index, 1);
+        // Removing an unknown device is a no-op.
+        cache.device_removed(&removed);
+        assert_eq!(cache.generation(), 3);
+    }
+
+    #[test]
+    fn test_device_index_allocation() {
+        let mut allocator = DeviceIndexAllocator::default();
+        assert_eq!(allocator.allocate("device-a"), 0);
+        assert_eq!(allocator.allocate("device-b"), 1);
+        assert_eq!(allocator.allocate("device-c"), 2);
+        assert!(allocator.is_allocated(1));
+    }
+
+    #[test]
+    fn test_device_index_free() {
+        let mut allocator = DeviceIndexAllocator::default();
+        allocator.allocate("device-a");
+        allocator.allocate("device-b");
+        allocator.free(0);
+        assert!(!allocator.is_allocated(0));
+        // New devices don't take an index reserved for a known device.
+        assert_eq!(allocator.allocate("device-c"), 2);
+    }
+
+    #[test]
+    fn test_device_index_reconnect() {
+        let mut allocator = DeviceIndexAllocator::default();
+        allocator.allocate("device-a");
+        assert_eq!(allocator.allocate("device-b"), 1);
+        assert_eq!(allocator.allocated_index("device-b"), Some(1));
+        allocator.free(1);
+        assert_eq!(allocator.allocated_index("device-b"), None);
+        allocator.allocate("device-c");
+        assert_eq!(allocator.allocate("device-b"), 1);
+        assert!(allocator.is_allocated(1));
+    }
+
+    #[test]
+    fn test_device_registry_stop_all_tries_every_device() {
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            VibrateCmd::MESSAGE_NAME.to_owned(),
+            MessageAttributes {
+                feature_count: Some(1),
+                patterns: None,
+            },
+        );
+        let protocols = [
+            TestProtocol::new(device_messages.clone()),
+            TestProtocol::failing(device_messages.clone()),
+            TestProtocol::new(device_messages),
+        ];
+        let registry = DeviceRegistry::default();
+        task::block_on(async {
+            for (device_index, protocol) in protocols.iter().enumerate() {
+                let address = device_index.to_string();
+                let device = test_device("Test Device", &address, protocol.clone()).await;
+                registry.insert(device_index as u32, device);
+            }
+            let err = registry.stop_all().await.unwrap_err();
+            assert_eq!(
+                messages::Error::from(err.clone()).error_code,
+                messages::ErrorCode::ErrorDevice
+            );
+            assert!(err.to_string().contains("Failed to stop devices: 1 ("));
+        });
+        for (device_index, protocol) in protocols.iter().enumerate() {
+            assert_eq!(
+                *protocol.received().lock().unwrap(),
+                vec![
+                    VibrateCmd::new(device_index as u32, vec![VibrateSubcommand::new(0, 0.0)])
+                        .as_union()
+                ]
+            );
+        }
+    }
+
+    struct TestSubtypeManager {
+        // Device to find, or None to fail scanning.
+        device: Option<Arc<TestDeviceImpl>>,
+        protocol: fn() -> Box<dyn ButtplugProtocol>,
+        scanning: bool,
+        // Disconnect and reconnect the device right after finding it.
+        reconnect: bool,
+    }
+
+    impl TestSubtypeManager {
+        fn new(address: &str) -> Self {
+            let name = format!("Test Device {}", address);
+            TestSubtypeManager::with_device(Arc::new(TestDeviceImpl::new(&name, address)))
+        }
+
+        fn with_device(device: Arc<TestDeviceImpl>) -> Self {
+            Self {
+                device: Some(device),
+                protocol: || Box::new(TestProtocol::default()),
+                scanning: false,
+                reconnect: false,
+            }
+        }
+
+        fn reconnecting(address: &str) -> Self {
+            Self {
+                reconnect: true,
+                ..TestSubtypeManager::new(address)
+            }
+        }
+
+        fn failing() -> Self {
+            Self {
+                device: None,
+                protocol: || Box::new(TestProtocol::default()),
+                scanning: false,
+                reconnect: false,
+            }
+        }
+    }
+
+    fn discovered_device(
+        device: Arc<TestDeviceImpl>,
+        protocol: fn() -> Box<dyn ButtplugProtocol>,
+    ) -> DiscoveredDevice {
+        let (address, name) = (device.address().to_owned(), device.name().to_owned());
+        DiscoveredDevice::new(&address, &name, move || {
+            ButtplugDevice::new(device, protocol())
+        })
+    }
+
+    #[async_trait]
+    impl DeviceSubtypeManager for TestSubtypeManager {
+        async fn start_scanning(
+            &mut self,
+            device_sender: Sender<SubtypeManagerEvent>,
+        ) -> Result<(), ButtplugError> {
+            match self.device {
+                Some(ref device) => {
+                    self.scanning = true;
+                    device_sender
+                        .send(SubtypeManagerEvent::DeviceFound(discovered_device(
+                            device.clone(),
+                            self.protocol,
+                        )))
+                        .await;
+                    if self.reconnect {
+                        device_sender
+                            .send(SubtypeManagerEvent::DeviceLost(device.address().to_owned()))
+                            .await;
+                        device_sender
+                            .send(SubtypeManagerEvent::DeviceFound(discovered_device(
+                                device.clone(),
+                                self.protocol,
+                            )))
+                            .await;
+                    }
+                    Ok(())
+                }
+                None => Err(ButtplugDeviceError::new("Test manager failed.").into()),
+            }
+        }
+
+        async fn stop_scanning(&mut self) -> Result<(), ButtplugError> {
+            self.scanning = false;
+            Ok(())
+        }
+
+        fn is_scanning(&self) -> bool {
+            self.scanning
+        }
+    }
+
+    #[test]
+    fn test_device_manager_multiple_subtype_managers() {
+        let (send, recv) = channel(256);
+        let mut manager = DeviceManager::new(send);
+        manager.add_subtype_manager(Box::new(TestSubtypeManager::new("first")));
+        manager.add_subtype_manager(Box::new(TestSubtypeManager::new("second")));
+        task::block_on(async {
+            manager.start_scanning().await.unwrap();
+            assert!(manager.is_scanning());
+            let mut names = vec![];
+            let mut indexes = vec![];
+            for _ in 0..2 {
+                match recv.recv().await.unwrap() {
+                    ButtplugMessageUnion::DeviceAdded(msg) => {
+                        names.push(msg.device_name);
+                        indexes.push(msg.device_index);
+                    }
+                    _ => panic!("Should've received device added"),
+                }
+            }
+            names.sort();
+            indexes.sort();
+            assert_eq!(names, vec!["Test Device first", "Test Device second"]);
+            assert_eq!(indexes, vec![0, 1]);
+            manager.stop_scanning().await.unwrap();
+            assert!(!manager.is_scanning());
+        });
+    }
+
+    #[test]
+    fn test_device_manager_device_lost() {
+        let (send, recv) = channel(256);
+        let mut manager = DeviceManager::new(send);
+        manager.add_subtype_manager(Box::new(TestSubtypeManager::reconnecting("flaky")));
+        task::block_on(async {
+            manager.start_scanning().await.unwrap();
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::DeviceAdded(msg) => assert_eq!(msg.device_index, 0),
+                _ => panic!("Should've received device added"),
+            }
+            assert_eq!(recv.recv().await.unwrap(), DeviceRemoved::new(0).as_union());
+            // The freed index goes back to the same device on reconnect.
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::DeviceAdded(msg) => assert_eq!(msg.device_index, 0),
+                _ => panic!("Should've received device added"),
+            }
+            assert!(manager.devices().device(0).is_some());
+        });
+    }
+
+    #[test]
+    fn test_device_filter_allow_all_by_default() {
+        let filter = DeviceFilter::default();
+        assert!(filter.is_allowed("00:11", "LVS-Hush"));
+    }
+
+    #[test]
+    fn test_device_filter_allow_list() {
+        let filter = DeviceFilter {
+            allow_list: vec!["00:11".to_owned(), "Launch*".to_owned()],
+            deny_list: vec![],
+        };
+        assert!(filter.is_allowed("00:11", "LVS-Hush"));
+        assert!(filter.is_allowed("00:22", "Launch"));
+        assert!(!filter.is_allowed("00:33", "LVS-Hush"));
+    }
+
+    #[test]
+    fn test_device_filter_deny_list() {
+        let filter = DeviceFilter {
+            allow_list: vec!["LVS-*".to_owned()],
+            deny_list: vec!["00:11".to_owned()],
+        };
+        assert!(!filter.is_allowed("00:11", "LVS-Hush"));
+        assert!(filter.is_allowed("00:22", "LVS-Hush"));
+    }
+
+    #[test]
+    fn test_device_manager_filtered_device() {
+        let (send, recv) = channel(256);
+        let mut manager = DeviceManager::new(send);
+        manager.set_device_filter(DeviceFilter {
+            allow_list: vec![],
+            deny_list: vec!["denied".to_owned()],
+        });
+        // Lovense devices are asked for their type when initialized.
+        let denied = Arc::new(TestDeviceImpl::new("LVS-Denied", "denied"));
+        let allowed = Arc::new(TestDeviceImpl::new("LVS-Allowed", "allowed"));
+        for device in &[denied.clone(), allowed.clone()] {
+            let mut subtype_manager = TestSubtypeManager::with_device(device.clone());
+            subtype_manager.protocol = || Box::new(LovenseProtocol::default());
+            manager.add_subtype_manager(Box::new(subtype_manager));
+        }
+        task::block_on(async {
+            allowed.add_reply(b"Z:11:0082059AD3BD;").await;
+            manager.start_scanning().await.unwrap();
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::DeviceAdded(msg) => {
+                    assert_eq!(msg.device_name, "LVS-Allowed");
+                }
+                _ => panic!("Should've received device added"),
+            }
+            assert_eq!(
+                allowed.take_written(),
+                vec![(Endpoint::Tx, b"DeviceType;".to_vec())]
+            );
+            assert!(denied.take_written().is_empty());
+            assert_eq!(manager.devices().device(0).unwrap().address(), "allowed");
+        });
+    }
+
+    #[test]
+    fn test_device_manager_subtype_manager_failure() {
+        let (send, recv) = channel(256);
+        let mut manager = DeviceManager::new(send);
+        manager.add_subtype_manager(Box::new(TestSubtypeManager::failing()));
+        task::block_on(async {
+            assert!(manager.start_scanning().await.is_err());
+            manager.add_subtype_manager(Box::new(TestSubtypeManager::new("working")));
+            manager.start_scanning().await.unwrap();
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::DeviceAdded(msg) => {
+                    assert_eq!(msg.device_name, "Test Device working");
+                }
+                _ => panic!("Should've received device added"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_device_manager_aliases() {
+        let (send, recv) = channel(256);
+        let mut manager = DeviceManager::new(send);
+        let mut aliases = HashMap::new();
+        aliases.insert("aliased".to_owned(), "Bedroom Hush".to_owned());
+        manager.set_device_aliases(aliases);
+        manager.add_subtype_manager(Box::new(TestSubtypeManager::new("aliased")));
+        manager.add_subtype_manager(Box::new(TestSubtypeManager::new("plain")));
+        task::block_on(async {
+            manager.start_scanning().await.unwrap();
+            let mut names = vec![];
+            for _ in 0..2 {
+                match recv.recv().await.unwrap() {
+                    ButtplugMessageUnion::DeviceAdded(msg) => {
+                        names.push(DeviceMessageInfo::from(&msg).device_name);
+                    }
+                    _ => panic!("Should've received device added"),
+                }
+            }
+            names.sort();
+            assert_eq!(names, vec!["Bedroom Hush", "Test Device plain"]);
+        });
+    }
+}
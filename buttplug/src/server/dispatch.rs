@@ -0,0 +1,137 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Routes incoming messages to async handlers by message type, with optional
+//! middleware run on every message beforehand.
+
+use crate::core::{
+    errors::{ButtplugError, ButtplugMessageError},
+    messages::{ButtplugMessageType, ButtplugMessageUnion},
+};
+use futures::future::{BoxFuture, Future, FutureExt};
+use std::collections::HashMap;
+
+/// What a message handler resolves to: the reply to send back to the client.
+pub type HandlerResult = Result<ButtplugMessageUnion, ButtplugError>;
+
+type Handler = Box<dyn Fn(ButtplugMessageUnion) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+type Middleware = Box<dyn Fn(&ButtplugMessageUnion) -> Result<(), ButtplugError> + Send + Sync>;
+
+/// Maps message names to handlers that receive the typed message.
+///
+/// Handlers are `'static`, so any state they need should be captured in the
+/// closure (usually behind an [std::sync::Arc]).
+#[derive(Default)]
+pub struct MessageDispatcher {
+    handlers: HashMap<&'static str, Handler>,
+    middleware: Vec<Middleware>,
+}
+
+impl MessageDispatcher {
+    /// Registers a handler for messages of type `T`, replacing any handler
+    /// previously registered for it.
+    pub fn add_handler<T, F, Fut>(&mut self, handler: F)
+    where
+        T: ButtplugMessageType + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let handler = move |msg: ButtplugMessageUnion| match T::try_from(msg) {
+            Ok(msg) => handler(msg).boxed(),
+            Err(msg) => {
+                let err = ButtplugMessageError::new(&format!(
+                    "Handler for {} received {}.",
+                    T::MESSAGE_NAME,
+                    msg.name()
+                ));
+                futures::future::ready(Err(err.into())).boxed()
+            }
+        };
+        self.handlers.insert(T::MESSAGE_NAME, Box::new(handler));
+    }
+
+    /// Adds a check that runs on every message before it is handled. If any
+    /// middleware returns an error, the message is rejected with it.
+    pub fn add_middleware<F>(&mut self, middleware: F)
+    where
+        F: Fn(&ButtplugMessageUnion) -> Result<(), ButtplugError> + Send + Sync + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// True if a handler is registered for the message's type.
+    pub fn has_handler(&self, msg: &ButtplugMessageUnion) -> bool {
+        self.handlers.contains_key(msg.name())
+    }
+
+    /// Runs all middleware against the message, in the order they were added.
+    pub fn run_middleware(&self, msg: &ButtplugMessageUnion) -> Result<(), ButtplugError> {
+        self.middleware.iter().try_for_each(|check| check(msg))
+    }
+
+    /// Hands the message to its registered handler. Returns [None] if no
+    /// handler is registered for the message type. Does not run middleware.
+    pub fn dispatch(&self, msg: ButtplugMessageUnion) -> Option<BoxFuture<'static, HandlerResult>> {
+        self.handlers.get(msg.name()).map(|handler| handler(msg))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{
+        errors::ButtplugDeviceError,
+        messages::{self, ButtplugMessage},
+    };
+    use async_std::task;
+
+    fn echo_dispatcher() -> MessageDispatcher {
+        let mut dispatcher = MessageDispatcher::default();
+        dispatcher.add_handler(|msg: messages::Test| async move { Ok(msg.as_union()) });
+        dispatcher
+    }
+
+    #[test]
+    fn test_dispatch_echo() {
+        let dispatcher = echo_dispatcher();
+        let msg = messages::Test::new("Echo");
+        assert!(dispatcher.has_handler(&msg.clone().as_union()));
+        task::block_on(async {
+            assert_eq!(
+                dispatcher
+                    .dispatch(msg.clone().as_union())
+                    .unwrap()
+                    .await
+                    .unwrap(),
+                msg.as_union()
+            );
+        });
+    }
+
+    #[test]
+    fn test_dispatch_no_handler() {
+        let dispatcher = echo_dispatcher();
+        assert!(dispatcher
+            .dispatch(messages::Ping::default().as_union())
+            .is_none());
+    }
+
+    #[test]
+    fn test_dispatch_middleware() {
+        let mut dispatcher = echo_dispatcher();
+        let msg = messages::Test::new("Echo").as_union();
+        assert!(dispatcher.run_middleware(&msg).is_ok());
+        dispatcher.add_middleware(|msg| match msg {
+            ButtplugMessageUnion::Test(_) => Err(ButtplugDeviceError::new("Rejected").into()),
+            _ => Ok(()),
+        });
+        assert!(dispatcher.run_middleware(&msg).is_err());
+        assert!(dispatcher
+            .run_middleware(&messages::Ping::default().as_union())
+            .is_ok());
+    }
+}
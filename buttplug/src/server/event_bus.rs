@@ -0,0 +1,179 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Broadcasts server events (device changes, errors, logs) to any number of
+//! subscribers.
+
+use crate::core::messages::ButtplugMessageUnion;
+use async_std::sync::{channel, Receiver, Sender};
+use futures::FutureExt;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+/// Number of events a subscriber can fall behind by before it starts missing
+/// events.
+const SUBSCRIBER_CAPACITY: usize = 256;
+
+/// Receiving end of an [EventBus] subscription. Derefs to the underlying
+/// [Receiver]. Dropping it unsubscribes.
+pub struct EventSubscriber {
+    receiver: Receiver<ButtplugMessageUnion>,
+    // The bus holds a weak reference to this, which also tells it when the
+    // subscriber is gone. Channels keep accepting sends after all receivers
+    // are dropped, so the sender can't tell.
+    dropped: Arc<AtomicUsize>,
+}
+
+impl EventSubscriber {
+    /// Number of events dropped so far because this subscriber fell too
+    /// far behind.
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+}
+
+impl Deref for EventSubscriber {
+    type Target = Receiver<ButtplugMessageUnion>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+struct Subscription {
+    sender: Sender<ButtplugMessageUnion>,
+    // Used to drop the oldest event when the subscriber falls behind.
+    receiver: Receiver<ButtplugMessageUnion>,
+    dropped: Weak<AtomicUsize>,
+    // Drop count when the subscriber last fell behind, while it still is.
+    lagging_since: Option<usize>,
+}
+
+/// Hands a copy of every published event to each subscriber.
+///
+/// Publishing never waits on subscribers. If a subscriber's queue is full,
+/// its oldest event is dropped to make room, so a slow logger or UI can't
+/// stall the server and always sees the latest events. Drops are counted
+/// (see [EventSubscriber::dropped_count]) and logged once the subscriber
+/// has caught up, rather than per event, since logs can be events too.
+/// Subscribers that have dropped their receiver are removed on the next
+/// publish.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscription>>>,
+}
+
+impl EventBus {
+    /// Returns a receiver for all events published from now on.
+    pub fn subscribe(&self) -> EventSubscriber {
+        let (sender, receiver) = channel(SUBSCRIBER_CAPACITY);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        self.subscribers.lock().unwrap().push(Subscription {
+            sender,
+            receiver: receiver.clone(),
+            dropped: Arc::downgrade(&dropped),
+            lagging_since: None,
+        });
+        EventSubscriber { receiver, dropped }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    pub fn publish(&self, msg: &ButtplugMessageUnion) {
+        let mut caught_up = vec![];
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscription| subscription.dropped.upgrade().is_some());
+        for subscription in subscribers.iter_mut() {
+            let dropped = match subscription.dropped.upgrade() {
+                Some(dropped) => dropped,
+                None => continue,
+            };
+            if subscription.sender.is_full() {
+                // The subscriber may have made room in the meantime, in
+                // which case nothing is dropped.
+                if subscription.receiver.recv().now_or_never().is_some() {
+                    let count = dropped.fetch_add(1, Ordering::SeqCst);
+                    subscription.lagging_since.get_or_insert(count);
+                }
+            } else if let Some(since) = subscription.lagging_since.take() {
+                caught_up.push(dropped.load(Ordering::SeqCst) - since);
+            }
+            // Sends to a channel with room finish immediately.
+            let _ = subscription.sender.send(msg.clone()).now_or_never();
+        }
+        drop(subscribers);
+        // Logged outside the lock, as logs can be published too.
+        for count in caught_up {
+            warn!(
+                "Event subscriber fell behind, {} events were dropped.",
+                count
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::messages::{self, ButtplugMessage};
+    use async_std::task;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_event_bus_multiple_subscribers() {
+        let bus = EventBus::default();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        let msg = messages::DeviceAdded::new(0, "Test Device", HashMap::new()).as_union();
+        bus.publish(&msg);
+        task::block_on(async {
+            assert_eq!(first.recv().await.unwrap(), msg);
+            assert_eq!(second.recv().await.unwrap(), msg);
+        });
+    }
+
+    #[test]
+    fn test_event_bus_slow_subscriber() {
+        let bus = EventBus::default();
+        let slow = bus.subscribe();
+        for i in 0..SUBSCRIBER_CAPACITY + 2 {
+            bus.publish(&messages::Ok::new(i as u32).as_union());
+        }
+        // The oldest events are dropped to make room for the newest.
+        assert_eq!(slow.len(), SUBSCRIBER_CAPACITY);
+        assert_eq!(slow.dropped_count(), 2);
+        task::block_on(async {
+            assert_eq!(slow.recv().await.unwrap(), messages::Ok::new(2).as_union());
+            // Caught up enough for the next event to fit.
+            bus.publish(&messages::Ok::new(1000).as_union());
+            assert_eq!(slow.dropped_count(), 2);
+            let mut last = None;
+            while let Some(msg) = slow.recv().now_or_never() {
+                last = msg;
+            }
+            assert_eq!(last, Some(messages::Ok::new(1000).as_union()));
+        });
+    }
+
+    #[test]
+    fn test_event_bus_dropped_subscriber() {
+        let bus = EventBus::default();
+        let kept = bus.subscribe();
+        drop(bus.subscribe());
+        assert_eq!(bus.subscriber_count(), 2);
+        bus.publish(&messages::Ok::new(1).as_union());
+        assert_eq!(bus.subscriber_count(), 1);
+        assert_eq!(kept.len(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Forwards log crate records to the client as Log messages, at the level the
+//! client asked for via RequestLog.
+
+use crate::core::messages::{self, ButtplugMessage, ButtplugMessageUnion, LogLevel};
+use async_std::{sync::Sender, task};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    StreamExt,
+};
+use std::sync::{Arc, Mutex};
+
+struct LogTarget {
+    log_level: LogLevel,
+    log_sender: UnboundedSender<ButtplugMessageUnion>,
+}
+
+/// A [log::Log] implementation that turns log records into [messages::Log]
+/// messages on the server event channel.
+///
+/// Nothing is forwarded until a client requests a log level. The bridge only
+/// sees records if it is installed as the process logger (see
+/// [ServerLogBridge::install]), or called from an application's own logger.
+#[derive(Default)]
+pub struct ServerLogBridge {
+    target: Mutex<Option<LogTarget>>,
+}
+
+impl ServerLogBridge {
+    /// Starts forwarding records at or above the given level to the event
+    /// sender, replacing any previous level. [LogLevel::Off] stops forwarding.
+    pub fn set_log_level(&self, log_level: LogLevel, event_sender: Sender<ButtplugMessageUnion>) {
+        let mut target = self.target.lock().unwrap();
+        if log_level == LogLevel::Off {
+            *target = None;
+            return;
+        }
+        // Log calls can come from anywhere, including outside of an async
+        // context, so queue messages and send them on from a task.
+        let (log_sender, mut log_receiver) = unbounded();
+        task::spawn(async move {
+            while let Some(msg) = log_receiver.next().await {
+                event_sender.send(msg).await;
+            }
+        });
+        *target = Some(LogTarget {
+            log_level,
+            log_sender,
+        });
+    }
+
+    /// Installs the bridge as the process wide logger. Fails if a logger has
+    /// already been set.
+    pub fn install(bridge: Arc<ServerLogBridge>) -> Result<(), log::SetLoggerError> {
+        log::set_logger(Box::leak(Box::new(SharedLogBridge(bridge))))?;
+        log::set_max_level(log::LevelFilter::Trace);
+        Ok(())
+    }
+}
+
+fn to_log_level(level: log::Level) -> LogLevel {
+    match level {
+        log::Level::Error => LogLevel::Error,
+        log::Level::Warn => LogLevel::Warn,
+        log::Level::Info => LogLevel::Info,
+        log::Level::Debug => LogLevel::Debug,
+        log::Level::Trace => LogLevel::Trace,
+    }
+}
+
+impl log::Log for ServerLogBridge {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        match *self.target.lock().unwrap() {
+            Some(ref target) => to_log_level(metadata.level()) <= target.log_level,
+            None => false,
+        }
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Some(ref target) = *self.target.lock().unwrap() {
+            let msg = messages::Log::new(to_log_level(record.level()), record.args().to_string());
+            // Only fails if the forwarding task is gone, in which case there's
+            // nowhere to send the message anyways.
+            let _ = target.log_sender.unbounded_send(msg.as_union());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+struct SharedLogBridge(Arc<ServerLogBridge>);
+
+impl log::Log for SharedLogBridge {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.log(record)
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::sync::channel;
+    use log::Log;
+
+    fn log_record(bridge: &ServerLogBridge, level: log::Level, message: &str) {
+        bridge.log(
+            &log::Record::builder()
+                .level(level)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn test_log_bridge_forwards_at_level() {
+        let (send, recv) = channel(256);
+        let bridge = ServerLogBridge::default();
+        log_record(&bridge, log::Level::Error, "Not requested yet");
+        bridge.set_log_level(LogLevel::Info, send);
+        log_record(&bridge, log::Level::Debug, "Too verbose");
+        log_record(&bridge, log::Level::Warn, "Test Warning");
+        task::block_on(async {
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::Log(msg) => {
+                    assert_eq!(msg.log_level, LogLevel::Warn);
+                    assert_eq!(msg.log_message, "Test Warning");
+                }
+                _ => panic!("Should've received log message"),
+            }
+            assert!(recv.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_log_bridge_off() {
+        let (send, recv) = channel(256);
+        let bridge = ServerLogBridge::default();
+        bridge.set_log_level(LogLevel::Trace, send.clone());
+        bridge.set_log_level(LogLevel::Off, send);
+        log_record(&bridge, log::Level::Error, "Test Error");
+        assert!(!bridge.enabled(&log::Metadata::builder().level(log::Level::Error).build()));
+        task::block_on(async {
+            task::sleep(std::time::Duration::from_millis(50)).await;
+            assert!(recv.is_empty());
+        });
+    }
+}
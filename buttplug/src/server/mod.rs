@@ -7,63 +7,507 @@
 
 //! Handles client sessions, as well as discovery and communication with hardware.
 
+pub mod clock;
 pub mod device_manager;
+pub mod dispatch;
+pub mod event_bus;
+pub mod log_bridge;
+mod ping_timer;
 
 use crate::core::errors::*;
-use crate::core::messages::{self, ButtplugMessage, ButtplugMessageUnion};
-use async_std::sync::Sender;
+use crate::core::messages::{
+    self, ButtplugMessage, ButtplugMessageType, ButtplugMessageUnion, DeviceMessageInfo,
+    MessageSpecVersion,
+};
+use async_std::{
+    sync::{channel, Sender},
+    task,
+};
+use clock::{Clock, SystemClock};
+use device_manager::{DeviceCommunicationManager, DeviceListCache, DeviceManager, DeviceRegistry};
+use dispatch::{HandlerResult, MessageDispatcher};
+use event_bus::EventBus;
+use futures::future::{self, Either, Future};
+use log_bridge::ServerLogBridge;
+use ping_timer::PingTimer;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 /// Represents a ButtplugServer.
 pub struct ButtplugServer {
-    server_name: String,
-    server_spec_version: u32,
-    client_spec_version: Option<u32>,
-    client_name: Option<String>,
-    max_ping_time: u32,
-    _event_sender: Sender<ButtplugMessageUnion>,
+    context: ServerContext,
+    handshake_timed_out: Arc<AtomicBool>,
+    dispatcher: MessageDispatcher,
+    event_bus: EventBus,
+    disconnected: bool,
 }
 
 impl ButtplugServer {
-    pub fn new(
-        name: &str,
-        max_ping_time: u32,
-        _event_sender: Sender<ButtplugMessageUnion>,
+    /// Creates a server that waits [DEFAULT_HANDSHAKE_TIMEOUT] milliseconds
+    /// for the client handshake, see [ButtplugServerBuilder] to change that.
+    pub fn new(name: &str, max_ping_time: u32, event_sender: Sender<ButtplugMessageUnion>) -> Self {
+        ButtplugServerBuilder::default()
+            .name(name)
+            .max_ping_time(max_ping_time)
+            .finish(event_sender)
+    }
+
+    fn from_builder(
+        builder: ButtplugServerBuilder,
+        event_sender: Sender<ButtplugMessageUnion>,
     ) -> Self {
-        Self {
-            server_name: name.to_string(),
-            server_spec_version: 1,
-            client_name: None,
-            client_spec_version: None,
-            max_ping_time,
-            _event_sender,
+        // Events from the ping timer, device manager etc. are routed through
+        // here so they also reach event bus subscribers. Device changes are
+        // applied to the device list before they go out, so a client that
+        // sees DeviceAdded can use the device right away. Only the copy sent
+        // to the client is downgraded to its spec version.
+        let event_bus = EventBus::default();
+        let device_list_cache = Arc::new(Mutex::new(DeviceListCache::default()));
+        let client_spec_version = Arc::new(Mutex::new(None));
+        let (internal_sender, internal_receiver) = channel::<ButtplugMessageUnion>(256);
+        let task_event_bus = event_bus.clone();
+        let task_device_list_cache = device_list_cache.clone();
+        let task_client_spec_version = client_spec_version.clone();
+        task::spawn(async move {
+            while let Some(msg) = internal_receiver.recv().await {
+                match msg {
+                    ButtplugMessageUnion::DeviceAdded(ref m) => {
+                        task_device_list_cache.lock().unwrap().device_added(m)
+                    }
+                    ButtplugMessageUnion::DeviceRemoved(ref m) => {
+                        task_device_list_cache.lock().unwrap().device_removed(m)
+                    }
+                    _ => (),
+                }
+                task_event_bus.publish(&msg);
+                let version = *task_client_spec_version.lock().unwrap();
+                match version {
+                    Some(version) => event_sender.send(msg.downgrade_to(version)).await,
+                    None => event_sender.send(msg).await,
+                }
+            }
+        });
+        let event_sender = internal_sender;
+        let mut device_manager = DeviceManager::new(event_sender.clone());
+        for comm_manager in builder.comm_managers {
+            device_manager.add_subtype_manager(comm_manager);
         }
+        let context = ServerContext {
+            server_name: builder.name,
+            server_spec_version: MessageSpecVersion::Version1,
+            max_ping_time: builder.max_ping_time,
+            client_name: Arc::new(Mutex::new(None)),
+            client_spec_version,
+            handshake_sender: Arc::new(Mutex::new(None)),
+            ping_timer: PingTimer::new(
+                builder.max_ping_time,
+                builder.clock.clone(),
+                device_manager.devices(),
+            ),
+            device_list_cache,
+            devices: device_manager.devices(),
+            device_manager: Arc::new(async_std::sync::Mutex::new(device_manager)),
+            log_bridge: Arc::new(ServerLogBridge::default()),
+            event_sender,
+        };
+        let mut dispatcher = MessageDispatcher::default();
+        context.add_handlers(&mut dispatcher);
+        let server = Self {
+            context,
+            handshake_timed_out: Arc::new(AtomicBool::new(false)),
+            dispatcher,
+            event_bus,
+            disconnected: false,
+        };
+        if builder.handshake_timeout > 0 {
+            server.start_handshake_timer(
+                builder.clock,
+                Duration::from_millis(builder.handshake_timeout.into()),
+            );
+        }
+        server
     }
 
+    /// Starts waiting for the client handshake. If no valid
+    /// [messages::RequestServerInfo] arrives within the timeout, the session
+    /// ends: devices are stopped, scanning and log forwarding stop, an
+    /// [messages::ErrorCode::ErrorHandshake] error is emitted on the event
+    /// channel and the server refuses all further messages. Connectors
+    /// should close the connection when they see the error.
+    fn start_handshake_timer(&self, clock: Arc<dyn Clock>, timeout: Duration) {
+        let (sender, handshake_receiver) = channel::<()>(1);
+        *self.context.handshake_sender.lock().unwrap() = Some(sender);
+        let timed_out = self.handshake_timed_out.clone();
+        let context = self.context.clone();
+        let deadline = clock.now() + timeout;
+        task::spawn(async move {
+            // The sender is dropped once the handshake finishes or the
+            // server goes away, which ends the wait without a timeout.
+            let handshake = Box::pin(handshake_receiver.recv());
+            if let Either::Left(_) = future::select(handshake, clock.sleep_until(deadline)).await {
+                return;
+            }
+            error!("Handshake timeout reached, no RequestServerInfo received from client.");
+            timed_out.store(true, Ordering::SeqCst);
+            context.end_session().await;
+            context
+                .event_sender
+                .send(
+                    messages::Error::new(
+                        messages::ErrorCode::ErrorHandshake,
+                        "Handshake timed out.",
+                    )
+                    .as_union(),
+                )
+                .await;
+        });
+    }
+
+    /// Handles a client message, returning the reply in the form the
+    /// client's spec version expects.
     pub async fn send_message(
         &mut self,
         msg: &ButtplugMessageUnion,
     ) -> Result<ButtplugMessageUnion, ButtplugError> {
-        match msg {
-            ButtplugMessageUnion::RequestServerInfo(ref _s) => self.perform_handshake(_s),
-            ButtplugMessageUnion::StartScanning(_) => {
-                self.start_scanning().await?;
-                Result::Ok(ButtplugMessageUnion::Ok(messages::Ok::new(msg.get_id())))
-            }
-            ButtplugMessageUnion::StopScanning(_) => {
-                self.stop_scanning().await?;
-                Result::Ok(ButtplugMessageUnion::Ok(messages::Ok::new(msg.get_id())))
-            }
-            ButtplugMessageUnion::RequestDeviceList(_) => {
-                let mut list = messages::DeviceList::default();
-                list.set_id(msg.get_id());
-                Result::Ok(list.as_union())
-            }
-            _ => Result::Ok(ButtplugMessageUnion::Ok(messages::Ok::new(msg.get_id()))),
+        let reply = self.handle_message(msg).await?;
+        Ok(match self.context.client_spec_version() {
+            Some(version) => reply.downgrade_to(version),
+            None => reply,
+        })
+    }
+
+    async fn handle_message(
+        &mut self,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError> {
+        if self.context.ping_timer.pinged_out() {
+            return Result::Err(ButtplugError::ButtplugPingError(ButtplugPingError::new(
+                "Server has pinged out, client must reconnect.",
+            )));
+        }
+        if self.handshake_timed_out.load(Ordering::SeqCst) {
+            return Result::Err(ButtplugError::ButtplugHandshakeError(
+                ButtplugHandshakeError::new("Handshake timed out, client must reconnect."),
+            ));
+        }
+        let is_handshake = matches!(msg, ButtplugMessageUnion::RequestServerInfo(_));
+        if self.context.client_spec_version().is_none() && !is_handshake {
+            return Result::Err(ButtplugError::ButtplugHandshakeError(
+                ButtplugHandshakeError::new(&format!(
+                    "RequestServerInfo must be the first message sent, received {}.",
+                    msg.name()
+                )),
+            ));
+        }
+        self.dispatcher.run_middleware(msg)?;
+        match self.dispatcher.dispatch(msg.clone()) {
+            Some(reply) => reply.await,
+            // Device messages that need no checks of their own go straight
+            // to their device.
+            None if msg.device_index().is_some() => self.context.send_device_message(msg).await,
+            None => Result::Ok(ButtplugMessageUnion::Ok(messages::Ok::new(msg.get_id()))),
         }
     }
 
+    /// Stops the ping timer without emitting an error, stops scanning and
+    /// log forwarding, and stops all devices so none are left running
+    /// without a client. Should be called when the client disconnects. Servers dropped
+    /// without disconnecting, for instance when a connection is lost, still
+    /// stop their devices.
+    pub async fn disconnect(&mut self) {
+        self.disconnected = true;
+        self.context.end_session().await;
+    }
+
+    /// Returns a receiver for server events (device changes, errors, logs),
+    /// in addition to the event sender the server was created with. Any
+    /// number of subscribers can be added, see [EventBus] for how slow
+    /// subscribers are handled.
+    pub fn subscribe_events(&self) -> event_bus::EventSubscriber {
+        self.event_bus.subscribe()
+    }
+
+    /// Returns the dispatcher used to route messages to handlers and
+    /// middleware. The server's own handling of each message type is
+    /// registered here, so a handler added for a message type replaces it.
+    /// Registering one for RequestServerInfo or Ping will break handshakes
+    /// and ping tracking. Ping and handshake checks always run before
+    /// middleware.
+    pub fn dispatcher_mut(&mut self) -> &mut MessageDispatcher {
+        &mut self.dispatcher
+    }
+
+    /// Returns the bridge that forwards log records to the client at the
+    /// level requested via RequestLog. It needs to be installed as (or called
+    /// from) the process logger to receive records, see
+    /// [ServerLogBridge::install].
+    pub fn log_bridge(&self) -> Arc<ServerLogBridge> {
+        self.context.log_bridge.clone()
+    }
+}
+
+impl Drop for ButtplugServer {
+    fn drop(&mut self) {
+        // Lets a pending handshake timer finish quietly.
+        *self.context.handshake_sender.lock().unwrap() = None;
+        if !self.disconnected {
+            let devices = self.context.devices.clone();
+            task::spawn(async move {
+                let _ = devices.stop_all().await;
+            });
+        }
+    }
+}
+
+/// Server state the message handlers work on. Handlers registered with the
+/// [MessageDispatcher] outlive any borrow of the server, so each gets its
+/// own clone.
+#[derive(Clone)]
+struct ServerContext {
+    server_name: String,
+    server_spec_version: MessageSpecVersion,
+    max_ping_time: u32,
+    client_name: Arc<Mutex<Option<String>>>,
+    // Shared with the event forwarding task, which downgrades events to it.
+    client_spec_version: Arc<Mutex<Option<MessageSpecVersion>>>,
+    handshake_sender: Arc<Mutex<Option<Sender<()>>>>,
+    ping_timer: PingTimer,
+    device_list_cache: Arc<Mutex<DeviceListCache>>,
+    device_manager: Arc<async_std::sync::Mutex<DeviceManager>>,
+    devices: DeviceRegistry,
+    log_bridge: Arc<ServerLogBridge>,
+    event_sender: Sender<ButtplugMessageUnion>,
+}
+
+impl ServerContext {
+    /// Registers the server's handling of each client message.
+    fn add_handlers(&self, dispatcher: &mut MessageDispatcher) {
+        self.add_handler(dispatcher, |context, msg: messages::RequestServerInfo| {
+            async move { context.perform_handshake(&msg) }
+        });
+        self.add_handler(dispatcher, |context, msg: messages::Ping| async move {
+            context.ping_timer.update_ping_time();
+            Ok(messages::Ok::new(msg.get_id()).as_union())
+        });
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::RequestLog| async move {
+                context
+                    .log_bridge
+                    .set_log_level(msg.log_level.clone(), context.event_sender.clone());
+                Ok(messages::Ok::new(msg.get_id()).as_union())
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::StopAllDevices| async move {
+                context.devices.stop_all().await?;
+                Ok(messages::Ok::new(msg.get_id()).as_union())
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::StartScanning| async move {
+                context.device_manager.lock().await.start_scanning().await?;
+                Ok(messages::Ok::new(msg.get_id()).as_union())
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::StopScanning| async move {
+                context.device_manager.lock().await.stop_scanning().await?;
+                Ok(messages::Ok::new(msg.get_id()).as_union())
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::RequestDeviceList| async move {
+                let device_list = context
+                    .device_list_cache
+                    .lock()
+                    .unwrap()
+                    .device_list(msg.get_id());
+                Ok(device_list.as_union())
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::VibrateCmd| async move {
+                msg.is_valid()?;
+                context.send_device_message(&msg.as_union()).await
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::SingleMotorVibrateCmd| {
+                async move {
+                    // Protocols only implement VibrateCmd, so this goes out as
+                    // one with every motor at the same speed.
+                    let vibrate_cmd =
+                        messages::VibrateCmd::from((&msg, context.vibrator_count(&msg)?));
+                    vibrate_cmd.is_valid()?;
+                    context.send_device_message(&vibrate_cmd.as_union()).await
+                }
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::OscillateCmd| async move {
+                msg.is_valid()?;
+                context.send_device_message(&msg.as_union()).await
+            },
+        );
+        self.add_handler(dispatcher, |context, msg: messages::RotateCmd| async move {
+            msg.is_valid()?;
+            context.send_device_message(&msg.as_union()).await
+        });
+        self.add_handler(dispatcher, |context, msg: messages::LinearCmd| async move {
+            msg.is_valid()?;
+            context.send_device_message(&msg.as_union()).await
+        });
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::FleshlightLaunchFW12Cmd| async move {
+                msg.is_valid()?;
+                context.send_device_message(&msg.as_union()).await
+            },
+        );
+        self.add_handler(
+            dispatcher,
+            |context, msg: messages::PatternCmd| async move {
+                let msg_union = msg.clone().as_union();
+                let device = context.check_device_message(&msg_union)?.unwrap();
+                msg.is_valid_for(&device.device_messages[msg_union.name()])
+                    .map_err(|_| {
+                        ButtplugDeviceError::new(&format!(
+                            "Device {} ({}) has no pattern named {}.",
+                            device.device_name, msg.device_index, msg.pattern_name
+                        ))
+                    })?;
+                context.forward_device_message(&msg_union).await
+            },
+        );
+    }
+
+    /// Registers a handler that gets its own clone of the context along
+    /// with the message.
+    fn add_handler<T, F, Fut>(&self, dispatcher: &mut MessageDispatcher, handler: F)
+    where
+        T: ButtplugMessageType + 'static,
+        F: Fn(ServerContext, T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let context = self.clone();
+        dispatcher.add_handler(move |msg: T| handler(context.clone(), msg));
+    }
+
+    fn client_spec_version(&self) -> Option<MessageSpecVersion> {
+        *self.client_spec_version.lock().unwrap()
+    }
+
+    /// Makes sure the device a message is addressed to exists, and
+    /// advertised support for the message type. Returns the device's info,
+    /// or [None] if the message isn't addressed to a device.
+    fn check_device_message(
+        &self,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<Option<DeviceMessageInfo>, ButtplugError> {
+        let device_index = match msg.device_index() {
+            Some(index) => index,
+            None => return Result::Ok(None),
+        };
+        let device = self
+            .device_list_cache
+            .lock()
+            .unwrap()
+            .device(device_index)
+            .cloned()
+            .ok_or_else(|| {
+                ButtplugDeviceError::new(&format!(
+                    "No device with index {} available.",
+                    device_index
+                ))
+            })?;
+        if !device.device_messages.contains_key(msg.name()) {
+            return Result::Err(ButtplugError::ButtplugDeviceError(
+                ButtplugDeviceError::new(&format!(
+                    "Device {} ({}) does not support {}.",
+                    device.device_name,
+                    device_index,
+                    msg.name()
+                )),
+            ));
+        }
+        Result::Ok(Some(device))
+    }
+
+    /// Returns the number of vibration motors the device a
+    /// SingleMotorVibrateCmd is addressed to advertised for VibrateCmd.
+    fn vibrator_count(&self, msg: &messages::SingleMotorVibrateCmd) -> Result<u32, ButtplugError> {
+        let device_index = msg.device_index;
+        let cache = self.device_list_cache.lock().unwrap();
+        let device = cache.device(device_index).ok_or_else(|| {
+            ButtplugDeviceError::new(&format!("No device with index {} available.", device_index))
+        })?;
+        device
+            .device_messages
+            .get(messages::VibrateCmd::MESSAGE_NAME)
+            .and_then(|attributes| attributes.feature_count)
+            .ok_or_else(|| {
+                ButtplugDeviceError::new(&format!(
+                    "Device {} ({}) does not support {}.",
+                    device.device_name,
+                    device_index,
+                    messages::VibrateCmd::MESSAGE_NAME
+                ))
+                .into()
+            })
+    }
+
+    /// Checks a device message against what its device advertised, then
+    /// hands it to the device and returns the device's reply.
+    async fn send_device_message(
+        &self,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError> {
+        self.check_device_message(msg)?;
+        self.forward_device_message(msg).await
+    }
+
+    /// Hands a device message that has already been checked with
+    /// [ServerContext::check_device_message] to its device.
+    async fn forward_device_message(
+        &self,
+        msg: &ButtplugMessageUnion,
+    ) -> Result<ButtplugMessageUnion, ButtplugError> {
+        let device_index = msg.device_index().unwrap();
+        // The device can disconnect between the check and here.
+        let device = self.devices.device(device_index).ok_or_else(|| {
+            ButtplugDeviceError::new(&format!("Device {} disconnected.", device_index))
+        })?;
+        device.parse_message(msg).await
+    }
+
+    /// Stops everything a client session keeps running: the handshake and
+    /// ping timers, scanning, log forwarding and all devices.
+    async fn end_session(&self) {
+        *self.handshake_sender.lock().unwrap() = None;
+        self.ping_timer.stop();
+        self.log_bridge
+            .set_log_level(messages::LogLevel::Off, self.event_sender.clone());
+        // Failures are logged by the device manager and registry, and
+        // there's no client left to tell.
+        let _ = self.device_manager.lock().await.stop_scanning().await;
+        let _ = self.devices.stop_all().await;
+    }
+
     fn perform_handshake(
-        &mut self,
+        &self,
         msg: &messages::RequestServerInfo,
     ) -> Result<ButtplugMessageUnion, ButtplugError> {
         if self.server_spec_version < msg.message_version {
@@ -71,13 +515,17 @@ impl ButtplugServer {
                 ButtplugHandshakeError {
                     message: format!(
                         "Server version ({}) must be equal to or greater than client version ({}).",
-                        self.server_spec_version, msg.message_version
+                        u32::from(self.server_spec_version),
+                        u32::from(msg.message_version)
                     ),
                 },
             ));
         }
-        self.client_name = Option::Some(msg.client_name.clone());
-        self.client_spec_version = Option::Some(msg.message_version);
+        check_client_name(&msg.client_name)?;
+        *self.client_name.lock().unwrap() = Option::Some(msg.client_name.clone());
+        *self.client_spec_version.lock().unwrap() = Option::Some(msg.message_version);
+        *self.handshake_sender.lock().unwrap() = None;
+        self.ping_timer.start(self.event_sender.clone());
         Result::Ok(
             messages::ServerInfo::new(
                 &self.server_name,
@@ -87,28 +535,124 @@ impl ButtplugServer {
             .as_union(),
         )
     }
+}
+
+/// Sets up a [ButtplugServer] along with the device communication managers it
+/// scans with.
+///
+/// ```
+/// # use buttplug::server::ButtplugServerBuilder;
+/// let (send, _recv) = async_std::sync::channel(256);
+/// let server = ButtplugServerBuilder::default()
+///     .name("My Server")
+///     .max_ping_time(1000)
+///     .finish(send);
+/// ```
+pub struct ButtplugServerBuilder {
+    name: String,
+    max_ping_time: u32,
+    handshake_timeout: u32,
+    clock: Arc<dyn Clock>,
+    comm_managers: Vec<Box<dyn DeviceCommunicationManager>>,
+}
+
+impl Default for ButtplugServerBuilder {
+    fn default() -> Self {
+        Self {
+            name: "Buttplug Server".to_owned(),
+            max_ping_time: 0,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            clock: Arc::new(SystemClock::default()),
+            comm_managers: vec![],
+        }
+    }
+}
+
+impl ButtplugServerBuilder {
+    /// Server name sent to clients in [messages::ServerInfo].
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
 
-    async fn start_scanning(&self) -> Result<(), ButtplugError> {
-        Ok(())
+    /// Milliseconds clients have between pings, 0 turns the ping timer off.
+    pub fn max_ping_time(mut self, max_ping_time: u32) -> Self {
+        self.max_ping_time = max_ping_time;
+        self
     }
 
-    async fn stop_scanning(&self) -> Result<(), ButtplugError> {
-        Ok(())
+    /// Milliseconds clients have to send [messages::RequestServerInfo] after
+    /// the server is built, 0 turns the timeout off.
+    pub fn handshake_timeout(mut self, handshake_timeout: u32) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
     }
+
+    /// Time source for the handshake and ping timers, the system clock by
+    /// default.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Adds a manager the server will scan for devices with.
+    pub fn add_comm_manager(mut self, comm_manager: Box<dyn DeviceCommunicationManager>) -> Self {
+        self.comm_managers.push(comm_manager);
+        self
+    }
+
+    /// Builds the server. Events (device changes, errors, logs) are sent to
+    /// the given sender.
+    pub fn finish(self, event_sender: Sender<ButtplugMessageUnion>) -> ButtplugServer {
+        ButtplugServer::from_builder(self, event_sender)
+    }
+}
+
+/// Milliseconds clients have to finish the handshake unless configured
+/// otherwise.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: u32 = 10_000;
+
+/// Longest client name accepted during handshake, in characters.
+const MAX_CLIENT_NAME_LENGTH: usize = 1024;
+
+/// Makes sure the client name is safe to show in logs and UIs: not empty, not
+/// overly long, and free of control characters.
+fn check_client_name(client_name: &str) -> Result<(), ButtplugError> {
+    let reason = if client_name.is_empty() {
+        "Client name cannot be empty.".to_owned()
+    } else if client_name.chars().count() > MAX_CLIENT_NAME_LENGTH {
+        format!(
+            "Client name cannot be longer than {} characters.",
+            MAX_CLIENT_NAME_LENGTH
+        )
+    } else if client_name.chars().any(char::is_control) {
+        "Client name cannot contain control characters.".to_owned()
+    } else {
+        return Result::Ok(());
+    };
+    Result::Err(ButtplugError::ButtplugHandshakeError(
+        ButtplugHandshakeError::new(&reason),
+    ))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use async_std::{sync::channel, task};
+    use crate::devices::test_device::{test_device, TestProtocol};
+    use async_std::{future, sync::channel, task};
+    use clock::ManualClock;
+    use std::{collections::HashMap, time::Duration};
 
     async fn test_server_setup(msg_union: &messages::ButtplugMessageUnion) -> ButtplugServer {
         let (send, _) = channel(256);
         let mut server = ButtplugServer::new("Test Server", 0, send);
-        assert_eq!(server.server_name, "Test Server");
+        assert_eq!(server.context.server_name, "Test Server");
         match server.send_message(&msg_union).await.unwrap() {
             ButtplugMessageUnion::ServerInfo(_s) => {
-                assert_eq!(_s, messages::ServerInfo::new("Test Server", 1, 0))
+                assert_eq!(
+                    _s,
+                    messages::ServerInfo::new("Test Server", MessageSpecVersion::Version1, 0)
+                )
             }
             _ => assert!(false, "Should've received ok"),
         }
@@ -117,34 +661,749 @@ mod test {
 
     #[test]
     fn test_server_handshake() {
-        let msg = messages::RequestServerInfo::new("Test Client", 1);
+        let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version1);
         let msg_union = ButtplugMessageUnion::RequestServerInfo(msg);
         task::block_on(async {
             let server = test_server_setup(&msg_union).await;
-            assert_eq!(server.client_name.unwrap(), "Test Client");
+            assert_eq!(
+                *server.context.client_name.lock().unwrap(),
+                Some("Test Client".to_owned())
+            );
+            assert_eq!(
+                server.context.client_spec_version(),
+                Some(MessageSpecVersion::Version1)
+            );
         });
     }
 
+    fn handshake_error_for_name(client_name: &str) -> Option<messages::ErrorCode> {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg = messages::RequestServerInfo::new(client_name, MessageSpecVersion::Version1);
+        task::block_on(async {
+            server
+                .send_message(&msg.as_union())
+                .await
+                .err()
+                .map(|err| messages::Error::from(err).error_code)
+        })
+    }
+
+    #[test]
+    fn test_server_client_name_validation() {
+        assert_eq!(
+            handshake_error_for_name(""),
+            Some(messages::ErrorCode::ErrorHandshake)
+        );
+        assert_eq!(
+            handshake_error_for_name(&"a".repeat(1025)),
+            Some(messages::ErrorCode::ErrorHandshake)
+        );
+        assert_eq!(
+            handshake_error_for_name("Test\nClient"),
+            Some(messages::ErrorCode::ErrorHandshake)
+        );
+        assert_eq!(handshake_error_for_name(&"a".repeat(1024)), None);
+        assert_eq!(handshake_error_for_name("テスト Client ✨"), None);
+    }
+
     #[test]
     fn test_server_version_lt() {
-        let msg = messages::RequestServerInfo::new("Test Client", 0);
+        let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version0);
         let msg_union = ButtplugMessageUnion::RequestServerInfo(msg);
         task::block_on(async {
             test_server_setup(&msg_union).await;
         });
     }
 
+    #[test]
+    fn test_server_invalid_vibrate_cmd() {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg = messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 2.0)]);
+        let msg_union = ButtplugMessageUnion::VibrateCmd(msg);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            let err = server.send_message(&msg_union).await.unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorMessage
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_invalid_rotate_cmd() {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg =
+            messages::RotateCmd::new(0, vec![messages::RotationSubcommand::new(0, 2.0, true)]);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            let err = server.send_message(&msg.as_union()).await.unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorMessage
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_empty_vibrate_cmd() {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg = messages::VibrateCmd::new(0, vec![]);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            let err =
+                messages::Error::from(server.send_message(&msg.as_union()).await.unwrap_err());
+            assert_eq!(err.error_code, messages::ErrorCode::ErrorMessage);
+            assert!(err.error_message.contains("at least one speed"));
+        });
+    }
+
+    #[test]
+    fn test_server_ping_timeout() {
+        let (send, recv) = channel(256);
+        let clock = ManualClock::default();
+        let mut server = ButtplugServerBuilder::default()
+            .max_ping_time(50)
+            .clock(Arc::new(clock.clone()))
+            .finish(send);
+        let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version1);
+        task::block_on(async {
+            server.send_message(&msg.as_union()).await.unwrap();
+            clock.advance(Duration::from_millis(50));
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::Error(_e) => {
+                    assert_eq!(_e.error_code, messages::ErrorCode::ErrorPing)
+                }
+                _ => panic!("Should've received ping error"),
+            }
+            let err = server
+                .send_message(&messages::Ping::default().as_union())
+                .await
+                .unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorPing
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_ping_timeout_stops_devices() {
+        let (send, recv) = channel(256);
+        let protocol = test_device_protocol();
+        let clock = ManualClock::default();
+        let mut server = ButtplugServerBuilder::default()
+            .max_ping_time(100)
+            .clock(Arc::new(clock.clone()))
+            .add_comm_manager(Box::new(TestSubtypeManager {
+                scanning: Arc::new(AtomicBool::new(false)),
+                protocol: protocol.clone(),
+            }))
+            .finish(send);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            scan_test_device(&mut server).await;
+            clock.advance(Duration::from_millis(99));
+            assert!(protocol.received().lock().unwrap().is_empty());
+            clock.advance(Duration::from_millis(1));
+            loop {
+                match recv.recv().await.unwrap() {
+                    ButtplugMessageUnion::Error(_e) => {
+                        assert_eq!(_e.error_code, messages::ErrorCode::ErrorPing);
+                        break;
+                    }
+                    ButtplugMessageUnion::DeviceAdded(_) => continue,
+                    _ => panic!("Should've received ping error"),
+                }
+            }
+            // Devices are stopped before the error goes out.
+            assert_eq!(
+                *protocol.received().lock().unwrap(),
+                vec![
+                    messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 0.0)])
+                        .as_union()
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_stop_all_devices() {
+        let protocol = test_device_protocol();
+        task::block_on(async {
+            let mut server = test_server_with_device(protocol.clone()).await;
+            server
+                .send_message(&messages::StopAllDevices::default().as_union())
+                .await
+                .unwrap();
+            assert_eq!(
+                *protocol.received().lock().unwrap(),
+                vec![
+                    messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 0.0)])
+                        .as_union()
+                ]
+            );
+        });
+    }
+
+    fn stopped_test_device() -> Vec<ButtplugMessageUnion> {
+        vec![
+            messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 0.0)]).as_union(),
+        ]
+    }
+
+    #[test]
+    fn test_server_disconnect_stops_devices() {
+        let protocol = test_device_protocol();
+        task::block_on(async {
+            let mut server = test_server_with_device(protocol.clone()).await;
+            server.disconnect().await;
+            assert_eq!(*protocol.received().lock().unwrap(), stopped_test_device());
+            // Already stopped, dropping doesn't stop again.
+            drop(server);
+            task::yield_now().await;
+        });
+        assert_eq!(*protocol.received().lock().unwrap(), stopped_test_device());
+    }
+
+    #[test]
+    fn test_server_dropped_connection_stops_devices() {
+        let protocol = test_device_protocol();
+        task::block_on(async {
+            let server = test_server_with_device(protocol.clone()).await;
+            drop(server);
+            let received = protocol.received();
+            future::timeout(Duration::from_secs(5), async {
+                while received.lock().unwrap().is_empty() {
+                    task::yield_now().await;
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(*received.lock().unwrap(), stopped_test_device());
+        });
+    }
+
+    #[test]
+    fn test_server_ping_keepalive() {
+        let (send, recv) = channel(256);
+        let clock = ManualClock::default();
+        let mut server = ButtplugServerBuilder::default()
+            .max_ping_time(100)
+            .clock(Arc::new(clock.clone()))
+            .finish(send);
+        let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version1);
+        task::block_on(async {
+            server.send_message(&msg.as_union()).await.unwrap();
+            for _ in 0..5 {
+                clock.advance(Duration::from_millis(40));
+                server
+                    .send_message(&messages::Ping::default().as_union())
+                    .await
+                    .unwrap();
+            }
+            // Well past the handshake, but within max_ping_time of the last
+            // ping.
+            clock.advance(Duration::from_millis(99));
+            server
+                .send_message(&messages::Ping::default().as_union())
+                .await
+                .unwrap();
+            server.disconnect().await;
+            clock.advance(Duration::from_millis(200));
+            // The timer task held the last sender besides the server's.
+            drop(server);
+            assert!(recv.recv().await.is_none());
+        });
+    }
+
+    struct TestSubtypeManager {
+        scanning: Arc<AtomicBool>,
+        protocol: TestProtocol,
+    }
+
+    impl TestSubtypeManager {
+        fn new(scanning: Arc<AtomicBool>) -> Self {
+            Self {
+                scanning,
+                protocol: test_device_protocol(),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl DeviceCommunicationManager for TestSubtypeManager {
+        async fn start_scanning(
+            &mut self,
+            device_sender: Sender<device_manager::SubtypeManagerEvent>,
+        ) -> Result<(), ButtplugError> {
+            self.scanning.store(true, Ordering::SeqCst);
+            let protocol = self.protocol.clone();
+            let discovered =
+                device_manager::DiscoveredDevice::new("test", "Test Device", || async move {
+                    Ok(test_device("Test Device", "test", protocol).await)
+                });
+            device_sender
+                .send(device_manager::SubtypeManagerEvent::DeviceFound(discovered))
+                .await;
+            Ok(())
+        }
+
+        async fn stop_scanning(&mut self) -> Result<(), ButtplugError> {
+            self.scanning.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn is_scanning(&self) -> bool {
+            self.scanning.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_server_builder() {
+        let (send, _) = channel(256);
+        let scanning = Arc::new(AtomicBool::new(false));
+        let mut server = ButtplugServerBuilder::default()
+            .name("Built Server")
+            .max_ping_time(500)
+            .add_comm_manager(Box::new(TestSubtypeManager::new(scanning.clone())))
+            .finish(send);
+        task::block_on(async {
+            let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version1);
+            assert_eq!(
+                server.send_message(&msg.as_union()).await.unwrap(),
+                messages::ServerInfo::new("Built Server", MessageSpecVersion::Version1, 500)
+                    .as_union()
+            );
+            server
+                .send_message(&messages::StartScanning::default().as_union())
+                .await
+                .unwrap();
+            assert!(scanning.load(Ordering::SeqCst));
+            server
+                .send_message(&messages::StopScanning::default().as_union())
+                .await
+                .unwrap();
+            assert!(!scanning.load(Ordering::SeqCst));
+            server.disconnect().await;
+        });
+    }
+
+    #[test]
+    fn test_server_event_subscribers() {
+        let (send, recv) = channel(256);
+        let mut server = ButtplugServerBuilder::default()
+            .add_comm_manager(Box::new(TestSubtypeManager::new(Arc::new(
+                AtomicBool::new(false),
+            ))))
+            .finish(send);
+        let first = server.subscribe_events();
+        let second = server.subscribe_events();
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            server
+                .send_message(&messages::StartScanning::default().as_union())
+                .await
+                .unwrap();
+            let expected =
+                messages::DeviceAdded::new(0, "Test Device", test_device_messages()).as_union();
+            // Only the client's copy is downgraded to its spec version.
+            assert_eq!(
+                recv.recv().await.unwrap(),
+                expected.downgrade_to(MessageSpecVersion::Version1)
+            );
+            assert_eq!(first.recv().await.unwrap(), expected);
+            assert_eq!(second.recv().await.unwrap(), expected);
+        });
+    }
+
+    async fn test_handshake(server: &mut ButtplugServer) {
+        let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version1);
+        server.send_message(&msg.as_union()).await.unwrap();
+    }
+
+    fn test_device_messages() -> HashMap<String, messages::MessageAttributes> {
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "VibrateCmd".to_owned(),
+            messages::MessageAttributes {
+                feature_count: Some(1),
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "OscillateCmd".to_owned(),
+            messages::MessageAttributes {
+                feature_count: Some(1),
+                patterns: None,
+            },
+        );
+        device_messages.insert(
+            "PatternCmd".to_owned(),
+            messages::MessageAttributes {
+                feature_count: None,
+                patterns: Some(vec!["Wave".to_owned()]),
+            },
+        );
+        device_messages
+    }
+
+    fn test_device_protocol() -> TestProtocol {
+        TestProtocol::new(test_device_messages())
+    }
+
+    /// Returns a server whose test device uses the given protocol.
+    fn test_device_server(protocol: TestProtocol) -> ButtplugServer {
+        let (send, _) = channel(256);
+        ButtplugServerBuilder::default()
+            .add_comm_manager(Box::new(TestSubtypeManager {
+                scanning: Arc::new(AtomicBool::new(false)),
+                protocol,
+            }))
+            .finish(send)
+    }
+
+    /// Scans until the test subtype manager's device has been added.
+    async fn scan_test_device(server: &mut ButtplugServer) {
+        let events = server.subscribe_events();
+        server
+            .send_message(&messages::StartScanning::default().as_union())
+            .await
+            .unwrap();
+        match events.recv().await.unwrap() {
+            ButtplugMessageUnion::DeviceAdded(_) => (),
+            _ => panic!("Should've received device added"),
+        }
+    }
+
+    /// Returns a server that has finished the handshake and has the test
+    /// device connected at index 0.
+    async fn test_server_with_device(protocol: TestProtocol) -> ButtplugServer {
+        let mut server = test_device_server(protocol);
+        test_handshake(&mut server).await;
+        scan_test_device(&mut server).await;
+        server
+    }
+
+    #[test]
+    fn test_server_scanned_device() {
+        let protocol = test_device_protocol();
+        task::block_on(async {
+            let mut server = test_server_with_device(protocol.clone()).await;
+            match server
+                .send_message(&messages::RequestDeviceList::default().as_union())
+                .await
+                .unwrap()
+            {
+                ButtplugMessageUnion::DeviceList(_l) => {
+                    assert_eq!(_l.devices.len(), 1);
+                    assert_eq!(_l.devices[0].device_index, 0);
+                    assert_eq!(_l.devices[0].device_name, "Test Device");
+                    // Messages newer than the client's spec version are left
+                    // out.
+                    assert_eq!(_l.devices[0].device_messages.len(), 1);
+                    assert!(_l.devices[0].device_messages.contains_key("VibrateCmd"));
+                }
+                _ => panic!("Should've received device list"),
+            }
+            let msg = messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 0.5)])
+                .as_union();
+            match server.send_message(&msg).await.unwrap() {
+                ButtplugMessageUnion::Ok(_) => (),
+                _ => panic!("Should've received ok"),
+            }
+            assert_eq!(*protocol.received().lock().unwrap(), vec![msg]);
+        });
+    }
+
+    #[test]
+    fn test_server_downgrades_to_client_version() {
+        let (send, recv) = channel(256);
+        let mut server = ButtplugServerBuilder::default()
+            .add_comm_manager(Box::new(TestSubtypeManager {
+                scanning: Arc::new(AtomicBool::new(false)),
+                protocol: test_device_protocol(),
+            }))
+            .finish(send);
+        let version0_messages = vec!["SingleMotorVibrateCmd".to_owned()];
+        task::block_on(async {
+            server
+                .send_message(
+                    &messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version0)
+                        .as_union(),
+                )
+                .await
+                .unwrap();
+            scan_test_device(&mut server).await;
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::DeviceAddedVersion0(m) => {
+                    assert_eq!(m.device_messages, version0_messages)
+                }
+                _ => panic!("Should've received a Version0 device added"),
+            }
+            match server
+                .send_message(&messages::RequestDeviceList::default().as_union())
+                .await
+                .unwrap()
+            {
+                ButtplugMessageUnion::DeviceListVersion0(_l) => {
+                    assert_eq!(_l.devices.len(), 1);
+                    assert_eq!(_l.devices[0].device_messages, version0_messages);
+                }
+                _ => panic!("Should've received a Version0 device list"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_server_single_motor_vibrate_cmd() {
+        let mut device_messages = HashMap::new();
+        device_messages.insert(
+            "VibrateCmd".to_owned(),
+            messages::MessageAttributes {
+                feature_count: Some(2),
+                patterns: None,
+            },
+        );
+        let protocol = TestProtocol::new(device_messages);
+        task::block_on(async {
+            let mut server = test_server_with_device(protocol.clone()).await;
+            let mut msg = messages::SingleMotorVibrateCmd::new(0, 0.5);
+            msg.set_id(7);
+            assert_eq!(
+                server.send_message(&msg.as_union()).await.unwrap(),
+                messages::Ok::new(7).as_union()
+            );
+            let mut expected = messages::VibrateCmd::new(
+                0,
+                vec![
+                    messages::VibrateSubcommand::new(0, 0.5),
+                    messages::VibrateSubcommand::new(1, 0.5),
+                ],
+            );
+            expected.set_id(7);
+            assert_eq!(
+                *protocol.received().lock().unwrap(),
+                vec![expected.as_union()]
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_unsupported_device_cmd() {
+        let msg =
+            messages::RotateCmd::new(0, vec![messages::RotationSubcommand::new(0, 0.5, true)]);
+        task::block_on(async {
+            let mut server = test_server_with_device(test_device_protocol()).await;
+            let err =
+                messages::Error::from(server.send_message(&msg.as_union()).await.unwrap_err());
+            assert_eq!(err.error_code, messages::ErrorCode::ErrorDevice);
+            assert!(err.error_message.contains("RotateCmd"));
+            let msg = messages::StopDeviceCmd::new(1);
+            let err =
+                messages::Error::from(server.send_message(&msg.as_union()).await.unwrap_err());
+            assert_eq!(err.error_code, messages::ErrorCode::ErrorDevice);
+        });
+    }
+
+    #[test]
+    fn test_server_dispatch_handler() {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        server
+            .dispatcher_mut()
+            .add_handler(|msg: messages::Test| async move { Ok(msg.as_union()) });
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            let msg = messages::Test::new("Echo");
+            assert_eq!(
+                server.send_message(&msg.clone().as_union()).await.unwrap(),
+                msg.as_union()
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_oscillate_cmd() {
+        task::block_on(async {
+            let mut server = test_server_with_device(test_device_protocol()).await;
+            let msg =
+                messages::OscillateCmd::new(0, vec![messages::OscillateSubcommand::new(0, 0.5)]);
+            match server.send_message(&msg.as_union()).await.unwrap() {
+                ButtplugMessageUnion::Ok(_) => (),
+                _ => panic!("Should've received ok"),
+            }
+            let msg =
+                messages::OscillateCmd::new(0, vec![messages::OscillateSubcommand::new(0, 1.5)]);
+            let err =
+                messages::Error::from(server.send_message(&msg.as_union()).await.unwrap_err());
+            assert_eq!(err.error_code, messages::ErrorCode::ErrorMessage);
+        });
+    }
+
+    #[test]
+    fn test_server_pattern_cmd() {
+        task::block_on(async {
+            let mut server = test_server_with_device(test_device_protocol()).await;
+            let msg = messages::PatternCmd::new(0, "Wave", None);
+            match server.send_message(&msg.as_union()).await.unwrap() {
+                ButtplugMessageUnion::Ok(_) => (),
+                _ => panic!("Should've received ok"),
+            }
+            let msg = messages::PatternCmd::new(0, "Pulse", Some(0));
+            let err =
+                messages::Error::from(server.send_message(&msg.as_union()).await.unwrap_err());
+            assert_eq!(err.error_code, messages::ErrorCode::ErrorDevice);
+            assert!(err.error_message.contains("Pulse"));
+        });
+    }
+
+    #[test]
+    fn test_server_request_log() {
+        use log::Log;
+        let (send, recv) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg = messages::RequestLog::new(messages::LogLevel::Info);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            server.send_message(&msg.as_union()).await.unwrap();
+            server.log_bridge().log(
+                &log::Record::builder()
+                    .level(log::Level::Warn)
+                    .args(format_args!("Test Warning"))
+                    .build(),
+            );
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::Log(_l) => {
+                    assert_eq!(_l.log_level, messages::LogLevel::Warn);
+                    assert_eq!(_l.log_message, "Test Warning");
+                }
+                _ => panic!("Should've received log message"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_server_invalid_linear_cmd() {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg = messages::LinearCmd::new(
+            0,
+            vec![
+                messages::VectorSubcommand::new(0, 500, 0.5),
+                messages::VectorSubcommand::new(0, 500, 0.2),
+            ],
+        );
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            let err = server.send_message(&msg.as_union()).await.unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorMessage
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_invalid_fleshlight_cmd() {
+        let (send, _) = channel(256);
+        let mut server = ButtplugServer::new("Test Server", 0, send);
+        let msg = messages::FleshlightLaunchFW12Cmd::new(0, 100, 99);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            let err = server.send_message(&msg.as_union()).await.unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorMessage
+            );
+        });
+    }
+
     #[test]
     fn test_server_version_gt() {
         let (send, _) = channel(256);
         let mut server = ButtplugServer::new("Test Server", 0, send);
-        let msg = messages::RequestServerInfo::new("Test Client", server.server_spec_version + 1);
+        let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version2);
         let msg_union = ButtplugMessageUnion::RequestServerInfo(msg);
         task::block_on(async {
-            assert!(
-                server.send_message(&msg_union).await.is_err(),
-                "Client having higher version than server should fail"
+            let err = server
+                .send_message(&msg_union)
+                .await
+                .expect_err("Client having higher version than server should fail");
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorHandshake
             );
+            assert!(server.context.client_spec_version().is_none());
+        });
+    }
+
+    #[test]
+    fn test_server_requires_handshake() {
+        let mut server = test_device_server(test_device_protocol());
+        let msg = messages::VibrateCmd::new(0, vec![messages::VibrateSubcommand::new(0, 0.5)]);
+        task::block_on(async {
+            let err = server
+                .send_message(&msg.clone().as_union())
+                .await
+                .unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorHandshake
+            );
+            test_handshake(&mut server).await;
+            scan_test_device(&mut server).await;
+            match server.send_message(&msg.as_union()).await.unwrap() {
+                ButtplugMessageUnion::Ok(_) => (),
+                _ => panic!("Should've received ok"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_server_handshake_timeout() {
+        let (send, recv) = channel(256);
+        let clock = ManualClock::default();
+        let mut server = ButtplugServerBuilder::default()
+            .handshake_timeout(50)
+            .clock(Arc::new(clock.clone()))
+            .finish(send);
+        clock.advance(Duration::from_millis(50));
+        task::block_on(async {
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::Error(_e) => {
+                    assert_eq!(_e.error_code, messages::ErrorCode::ErrorHandshake)
+                }
+                _ => panic!("Should've received handshake error"),
+            }
+            let msg = messages::RequestServerInfo::new("Test Client", MessageSpecVersion::Version1);
+            let err = server.send_message(&msg.as_union()).await.unwrap_err();
+            assert_eq!(
+                messages::Error::from(err).error_code,
+                messages::ErrorCode::ErrorHandshake
+            );
+        });
+    }
+
+    #[test]
+    fn test_server_handshake_before_timeout() {
+        let (send, recv) = channel(256);
+        let clock = ManualClock::default();
+        let mut server = ButtplugServerBuilder::default()
+            .handshake_timeout(50)
+            .clock(Arc::new(clock.clone()))
+            .finish(send);
+        task::block_on(async {
+            test_handshake(&mut server).await;
+            clock.advance(Duration::from_millis(100));
+            // Nothing but the server is left to send events once the timer
+            // task has ended.
+            drop(server);
+            assert!(recv.recv().await.is_none());
         });
     }
 }
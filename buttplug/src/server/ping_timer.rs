@@ -0,0 +1,158 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2019 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Ping timer, enforces the max_ping_time sent to the client during handshake.
+
+use super::{clock::Clock, device_manager::DeviceRegistry};
+use crate::core::messages::{self, ButtplugMessage, ButtplugMessageUnion, ErrorCode};
+use async_std::{
+    sync::{channel, Sender},
+    task,
+};
+use futures::future::{self, Either};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Watches for pings from the client. If no ping is received within
+/// max_ping_time milliseconds, all devices are stopped, an
+/// [ErrorCode::ErrorPing] error is emitted on the server event channel and
+/// the timer is marked as pinged out.
+///
+/// Clones share the same timer. The timer task ends once the timer is
+/// stopped, or every clone has been dropped.
+#[derive(Clone)]
+pub struct PingTimer {
+    max_ping_time: u32,
+    clock: Arc<dyn Clock>,
+    last_ping: Arc<Mutex<Duration>>,
+    stop_sender: Arc<Mutex<Option<Sender<()>>>>,
+    pinged_out: Arc<AtomicBool>,
+    devices: DeviceRegistry,
+}
+
+impl PingTimer {
+    pub fn new(max_ping_time: u32, clock: Arc<dyn Clock>, devices: DeviceRegistry) -> Self {
+        Self {
+            max_ping_time,
+            last_ping: Arc::new(Mutex::new(clock.now())),
+            clock,
+            stop_sender: Arc::new(Mutex::new(None)),
+            pinged_out: Arc::new(AtomicBool::new(false)),
+            devices,
+        }
+    }
+
+    /// Starts the timer task. Does nothing if max_ping_time is 0, or if the
+    /// timer is already running.
+    pub fn start(&self, event_sender: Sender<ButtplugMessageUnion>) {
+        let mut stop_sender = self.stop_sender.lock().unwrap();
+        if self.max_ping_time == 0 || stop_sender.is_some() {
+            return;
+        }
+        let (sender, stop_receiver) = channel::<()>(1);
+        *stop_sender = Some(sender);
+        self.update_ping_time();
+        let clock = self.clock.clone();
+        let last_ping = self.last_ping.clone();
+        let pinged_out = self.pinged_out.clone();
+        let devices = self.devices.clone();
+        let max_ping_time = Duration::from_millis(u64::from(self.max_ping_time));
+        task::spawn(async move {
+            let mut deadline = *last_ping.lock().unwrap() + max_ping_time;
+            loop {
+                let stopped = Box::pin(stop_receiver.recv());
+                // Nothing is ever sent on the stop channel, recv only
+                // resolves once the sender is dropped.
+                if let Either::Left(_) = future::select(stopped, clock.sleep_until(deadline)).await
+                {
+                    break;
+                }
+                // Pings only move the deadline, check whether one came in
+                // while we slept.
+                let next_deadline = *last_ping.lock().unwrap() + max_ping_time;
+                if next_deadline > deadline {
+                    deadline = next_deadline;
+                    continue;
+                }
+                error!("Ping timeout reached, no ping received from client.");
+                pinged_out.store(true, Ordering::SeqCst);
+                // Failures are logged by stop_all, and there's no one left
+                // to report them to.
+                let _ = devices.stop_all().await;
+                event_sender
+                    .send(messages::Error::new(ErrorCode::ErrorPing, "Ping timed out.").as_union())
+                    .await;
+                break;
+            }
+        });
+    }
+
+    /// Resets the timer. Should be called every time the server receives a
+    /// [messages::Ping].
+    pub fn update_ping_time(&self) {
+        *self.last_ping.lock().unwrap() = self.clock.now();
+    }
+
+    /// Stops the timer task without emitting an error. Used on clean
+    /// disconnect.
+    pub fn stop(&self) {
+        *self.stop_sender.lock().unwrap() = None;
+    }
+
+    /// True if the client failed to ping within max_ping_time.
+    pub fn pinged_out(&self) -> bool {
+        self.pinged_out.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::clock::ManualClock;
+
+    #[test]
+    fn test_ping_timer_ping_moves_deadline() {
+        let (send, recv) = channel(256);
+        let clock = ManualClock::default();
+        let timer = PingTimer::new(100, Arc::new(clock.clone()), DeviceRegistry::default());
+        timer.start(send);
+        task::block_on(async {
+            clock.advance(Duration::from_millis(60));
+            timer.update_ping_time();
+            // Past the first deadline, but not the one set by the ping.
+            clock.advance(Duration::from_millis(60));
+            assert!(!timer.pinged_out());
+            clock.advance(Duration::from_millis(40));
+            match recv.recv().await.unwrap() {
+                ButtplugMessageUnion::Error(e) => assert_eq!(e.error_code, ErrorCode::ErrorPing),
+                _ => panic!("Should've received ping error"),
+            }
+            assert!(timer.pinged_out());
+        });
+    }
+
+    #[test]
+    fn test_ping_timer_stop() {
+        let (send, recv) = channel(256);
+        let clock = ManualClock::default();
+        let timer = PingTimer::new(100, Arc::new(clock.clone()), DeviceRegistry::default());
+        timer.start(send);
+        timer.stop();
+        clock.advance(Duration::from_millis(200));
+        task::block_on(async {
+            // The timer task holds the only other sender, so the channel
+            // closes once the task has ended.
+            assert!(recv.recv().await.is_none());
+        });
+        assert!(!timer.pinged_out());
+    }
+}
@@ -15,9 +15,50 @@ pub fn buttplug_message_derive(input: TokenStream) -> TokenStream {
     impl_buttplug_message_macro(&ast)
 }
 
+fn find_id_field(ast: &syn::DeriveInput) -> Option<&syn::Field> {
+    if let syn::Data::Struct(ref data) = ast.data {
+        if let syn::Fields::Named(ref fields) = data.fields {
+            return fields
+                .named
+                .iter()
+                .find(|field| field.ident.as_ref().map(|ident| ident == "id") == Some(true));
+        }
+    }
+    None
+}
+
+fn is_u32(ty: &syn::Type) -> bool {
+    if let syn::Type::Path(ref type_path) = ty {
+        return type_path.qself.is_none() && type_path.path.is_ident("u32");
+    }
+    false
+}
+
 fn impl_buttplug_message_macro(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
+    // Without these checks, a missing or mistyped id field shows up as an
+    // error pointing into the generated get_id/set_id code, which is hard to
+    // make sense of.
+    const ID_ERROR: &str = "ButtplugMessage requires a `u32` field named `id`";
+    match find_id_field(ast) {
+        None => {
+            return syn::Error::new_spanned(name, ID_ERROR)
+                .to_compile_error()
+                .into();
+        }
+        Some(field) if !is_u32(&field.ty) => {
+            return syn::Error::new_spanned(&field.ty, ID_ERROR)
+                .to_compile_error()
+                .into();
+        }
+        _ => {}
+    }
+    let message_name = name.to_string();
     let gen = quote! {
+        impl ButtplugMessageType for #name {
+            const MESSAGE_NAME: &'static str = #message_name;
+        }
+
         impl ButtplugMessage for #name {
             fn get_id(&self) -> u32 {
                 self.id
@@ -31,6 +72,19 @@ fn impl_buttplug_message_macro(ast: &syn::DeriveInput) -> TokenStream {
                 ButtplugMessageUnion::#name(self)
             }
         }
+
+        impl std::convert::TryFrom<ButtplugMessageUnion> for #name {
+            type Error = ButtplugMessageUnion;
+
+            /// Unwraps the message from the union, handing the union back if
+            /// it holds a different message type.
+            fn try_from(msg: ButtplugMessageUnion) -> Result<Self, Self::Error> {
+                match msg {
+                    ButtplugMessageUnion::#name(msg) => Ok(msg),
+                    _ => Err(msg),
+                }
+            }
+        }
     };
     gen.into()
 }
@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate buttplug_derive;
+
+#[derive(ButtplugMessage)]
+struct MissingId {
+    device_index: u32,
+}
+
+fn main() {}
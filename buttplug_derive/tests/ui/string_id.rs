@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate buttplug_derive;
+
+#[derive(ButtplugMessage)]
+struct StringId {
+    id: String,
+}
+
+fn main() {}